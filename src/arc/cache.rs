@@ -0,0 +1,380 @@
+use super::*;
+use std::io::{BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+use util::read_struct;
+
+const MAGIC: [u8; 8] = *b"ARCINDEX";
+const VERSION: u32 = 2;
+
+const DIRECTORY: u8 = 0;
+const UNCOMPRESSED: u8 = 1;
+const COMPRESSED: u8 = 2;
+
+/// Identifies a specific version of an arc file, so a cached index is only used for the arc
+/// it was built from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheKey {
+    pub size: u64,
+    pub mtime: u64,
+    pub header_hash: u32,
+    pub labels_len: u64,
+}
+
+impl CacheKey {
+    pub fn new<P: AsRef<Path>>(path: P, header: &ArcHeader) -> io::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        let mtime = metadata.modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0);
+        let header_bytes = unsafe {
+            std::slice::from_raw_parts(
+                header as *const ArcHeader as *const u8,
+                std::mem::size_of::<ArcHeader>()
+            )
+        };
+
+        Ok(CacheKey {
+            size: metadata.len(),
+            mtime,
+            header_hash: crc32(header_bytes),
+            labels_len: HASH_STRINGS.len() as u64,
+        })
+    }
+
+    pub fn file_name(&self) -> PathBuf {
+        format!("{:x}-{:x}-{:08x}.idx", self.size, self.mtime, self.header_hash).into()
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.size.to_le_bytes())?;
+        writer.write_all(&self.mtime.to_le_bytes())?;
+        writer.write_all(&self.header_hash.to_le_bytes())?;
+        writer.write_all(&self.labels_len.to_le_bytes())
+    }
+
+    fn read<R: Read>(reader: &mut R) -> io::Result<Self> {
+        Ok(CacheKey {
+            size: read_struct(&mut *reader)?,
+            mtime: read_struct(&mut *reader)?,
+            header_hash: read_struct(&mut *reader)?,
+            labels_len: read_struct(&mut *reader)?,
+        })
+    }
+}
+
+/// The tree of an `Arc`, as stored on disk
+pub struct Index {
+    pub names: HashMap<u64, ArcStr>,
+    pub stream_paths: HashMap<u64, ArcStr>,
+    pub dir_children: HashMap<u64, HashSet<u64>>,
+    pub files: HashMap<u64, ArcFileInfo>,
+    pub stems: HashMap<u64, ArcStr>,
+}
+
+/// All the strings of an index are stored in a single buffer and referenced by offset/length
+#[derive(Default)]
+struct StringTable {
+    data: String,
+    offsets: HashMap<ArcStr, (u32, u32)>,
+}
+
+impl StringTable {
+    fn add(&mut self, string: ArcStr) -> (u32, u32) {
+        let data = &mut self.data;
+        *self.offsets.entry(string).or_insert_with(|| {
+            let offset = data.len() as u32;
+            data.push_str(string);
+            (offset, string.len() as u32)
+        })
+    }
+}
+
+fn write_strings<W: Write>(
+    writer: &mut W,
+    strings: &mut StringTable,
+    map: &HashMap<u64, ArcStr>
+) -> io::Result<()> {
+    writer.write_all(&(map.len() as u64).to_le_bytes())?;
+    for (hash40, string) in map {
+        let (offset, len) = strings.add(string);
+        writer.write_all(&hash40.to_le_bytes())?;
+        writer.write_all(&offset.to_le_bytes())?;
+        writer.write_all(&len.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// `count` read from the index as a usize, failing if `count` records of at least `size` bytes
+/// can't fit in the `remaining` bytes. Stops a corrupt count from causing a huge allocation
+fn checked_count(count: u64, size: usize, remaining: &[u8]) -> io::Result<usize> {
+    match count.checked_mul(size as u64) {
+        Some(len) if len <= remaining.len() as u64 => Ok(count as usize),
+        _ => Err(invalid_data("count past the end of the index")),
+    }
+}
+
+fn read_strings(reader: &mut &[u8], data: ArcStr) -> io::Result<HashMap<u64, ArcStr>> {
+    let count: u64 = read_struct(&mut *reader)?;
+    (0..checked_count(count, 16, reader)?)
+        .map(|_| {
+            let hash40: u64 = read_struct(&mut *reader)?;
+            let offset: u32 = read_struct(&mut *reader)?;
+            let len: u32 = read_struct(&mut *reader)?;
+            let string = data.get(offset as usize..(offset + len) as usize)
+                .ok_or_else(|| invalid_data("string out of bounds"))?;
+
+            Ok((hash40, string))
+        })
+        .collect()
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Every hash the tables reference, so labels of extensions and file names are kept along with
+/// the paths in the tree
+fn table_hashes(arc: &Arc) -> HashSet<u64> {
+    let tables = &arc.tables;
+    let paths = tables.file_info_paths.iter().flat_map(|path| {
+        vec![path.path.hash40(), path.ext.hash40(), path.parent.hash40(), path.file_name.hash40()]
+    });
+    let directories = tables.directories.iter().flat_map(|dir| {
+        vec![dir.hash40(), dir.name.hash40(), dir.parent.hash40()]
+    });
+
+    arc.files.keys().copied().chain(paths).chain(directories).collect()
+}
+
+/// Write the tree of `arc` to `path`. The index is written next to it first and renamed into
+/// place, so an interrupted write never leaves a truncated index behind
+pub fn write_index<P: AsRef<Path>>(path: P, key: &CacheKey, arc: &Arc) -> io::Result<()> {
+    let path = path.as_ref();
+    let temp_path = path.with_extension("idx.tmp");
+    let written = File::create(&temp_path).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        write_index_to(&mut writer, key, arc)?;
+        writer.into_inner().map_err(|err| err.into_error())?.sync_data()
+    });
+    if let Err(err) = written {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(err)
+    }
+
+    std::fs::rename(&temp_path, path)
+}
+
+fn write_index_to<W: Write>(mut writer: W, key: &CacheKey, arc: &Arc) -> io::Result<()> {
+    let names: HashMap<u64, ArcStr> = table_hashes(arc)
+        .into_iter()
+        .filter_map(|hash40| Some((hash40, *arc.names.get(&hash40)?)))
+        .collect();
+
+    let mut strings = StringTable::default();
+    for string in names.values().chain(arc.stream_paths.values()).chain(arc.stems.values()) {
+        strings.add(string);
+    }
+
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    key.write(&mut writer)?;
+
+    writer.write_all(&(strings.data.len() as u64).to_le_bytes())?;
+    writer.write_all(strings.data.as_bytes())?;
+    write_strings(&mut writer, &mut strings, &names)?;
+    write_strings(&mut writer, &mut strings, &arc.stream_paths)?;
+    write_strings(&mut writer, &mut strings, &arc.stems)?;
+
+    writer.write_all(&(arc.files.len() as u64).to_le_bytes())?;
+    for (hash40, file) in &arc.files {
+        let (kind, offset, size, extra) = match *file {
+            ArcFileInfo::Directory | ArcFileInfo::None => (DIRECTORY, 0, 0, 0),
+            ArcFileInfo::Uncompressed { data, flags } => {
+                (UNCOMPRESSED, data.inner_ptr() as u64, data.len() as u64, flags as u64)
+            }
+            ArcFileInfo::Compressed { data, decomp_size, flags } => {
+                (COMPRESSED, data.inner_ptr() as u64, data.len() as u64, decomp_size | ((flags as u64) << 32))
+            }
+        };
+        writer.write_all(&hash40.to_le_bytes())?;
+        writer.write_all(&[kind])?;
+        writer.write_all(&offset.to_le_bytes())?;
+        writer.write_all(&size.to_le_bytes())?;
+        writer.write_all(&extra.to_le_bytes())?;
+    }
+
+    writer.write_all(&(arc.dir_children.len() as u64).to_le_bytes())?;
+    for (hash40, children) in &arc.dir_children {
+        writer.write_all(&hash40.to_le_bytes())?;
+        writer.write_all(&(children.len() as u32).to_le_bytes())?;
+        for child in children {
+            writer.write_all(&child.to_le_bytes())?;
+        }
+    }
+
+    writer.flush()
+}
+
+/// Read an index previously written by `write_index`, failing if it doesn't match `key`
+pub fn read_index<P: AsRef<Path>>(path: P, key: &CacheKey) -> io::Result<Index> {
+    let index = std::fs::read(path)?;
+    let mut reader = &index[..];
+
+    let magic: [u8; 8] = read_struct(&mut reader)?;
+    let version: u32 = read_struct(&mut reader)?;
+    if magic != MAGIC || version != VERSION {
+        return Err(invalid_data("not an index or unsupported version"));
+    }
+    if CacheKey::read(&mut reader)? != *key {
+        return Err(invalid_data("index is for a different arc"));
+    }
+
+    let len: u64 = read_struct(&mut reader)?;
+    let mut data = vec![0; checked_count(len, 1, reader)?];
+    reader.read_exact(&mut data)?;
+    let data = String::from_utf8(data).map_err(|_| invalid_data("string table isn't utf8"))?;
+
+    let data: ArcStr = Box::leak(data.into_boxed_str());
+    let names = read_strings(&mut reader, data)?;
+    let stream_paths = read_strings(&mut reader, data)?;
+    let stems = read_strings(&mut reader, data)?;
+
+    let count: u64 = read_struct(&mut reader)?;
+    let files = (0..checked_count(count, 33, reader)?)
+        .map(|_| {
+            let hash40: u64 = read_struct(&mut reader)?;
+            let kind: u8 = read_struct(&mut reader)?;
+            let offset: u64 = read_struct(&mut reader)?;
+            let size: u64 = read_struct(&mut reader)?;
+            let extra: u64 = read_struct(&mut reader)?;
+            let data = FileSlice::new(offset as usize, size as usize);
+            let file = match kind {
+                DIRECTORY => ArcFileInfo::Directory,
+                UNCOMPRESSED => ArcFileInfo::Uncompressed { data, flags: extra as u32 },
+                COMPRESSED => ArcFileInfo::Compressed {
+                    data,
+                    decomp_size: extra & 0xffff_ffff,
+                    flags: (extra >> 32) as u32,
+                },
+                _ => return Err(invalid_data("unknown file kind")),
+            };
+
+            Ok((hash40, file))
+        })
+        .collect::<io::Result<_>>()?;
+
+    let count: u64 = read_struct(&mut reader)?;
+    let dir_children = (0..checked_count(count, 12, reader)?)
+        .map(|_| {
+            let hash40: u64 = read_struct(&mut reader)?;
+            let child_count: u32 = read_struct(&mut reader)?;
+            let children = (0..checked_count(child_count as u64, 8, reader)?)
+                .map(|_| read_struct(&mut reader))
+                .collect::<io::Result<_>>()?;
+
+            Ok((hash40, children))
+        })
+        .collect::<io::Result<_>>()?;
+
+    Ok(Index { names, stream_paths, dir_children, files, stems })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn index_round_trip() {
+        let arc_path = fixture::sample().write("cache");
+        let arc = fixture::sample().open_path(&arc_path);
+        let key = CacheKey::new(&arc_path, &arc.tables.arc_header).unwrap();
+        let index_path = arc_path.with_extension("idx");
+        write_index(&index_path, &key, &arc).unwrap();
+        assert!(!index_path.with_extension("idx.tmp").exists());
+
+        let index = read_index(&index_path, &key).unwrap();
+        let mut files: Vec<_> = index.files.keys().collect();
+        files.sort();
+        let mut expected: Vec<_> = arc.files.keys().collect();
+        expected.sort();
+        assert_eq!(files, expected);
+        assert_eq!(index.dir_children, arc.dir_children);
+        assert_eq!(index.stems, arc.stems);
+        assert_eq!(index.stream_paths, arc.stream_paths);
+        // Extension and file name labels are kept along with the paths
+        assert_eq!(index.names.get(&hash40("numdlb")), Some(&"numdlb"));
+        assert_eq!(index.names.get(&hash40("model.numdlb")), Some(&"model.numdlb"));
+        assert_eq!(index.names.get(&hash40("fighter/mario/model.numdlb")), Some(&"fighter/mario/model.numdlb"));
+
+        std::fs::remove_file(index_path).unwrap();
+        std::fs::remove_file(arc_path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_index() {
+        let arc_path = fixture::sample().write("corrupt-cache");
+        let arc = fixture::sample().open_path(&arc_path);
+        let key = CacheKey::new(&arc_path, &arc.tables.arc_header).unwrap();
+        let index_path = arc_path.with_extension("idx");
+        write_index(&index_path, &key, &arc).unwrap();
+        let index = std::fs::read(&index_path).unwrap();
+
+        // Truncated anywhere
+        for len in [0, 20, index.len() / 2, index.len() - 1] {
+            std::fs::write(&index_path, &index[..len]).unwrap();
+            assert!(read_index(&index_path, &key).is_err());
+        }
+
+        // A string table length far past the end of the file
+        let len_offset = MAGIC.len() + 4 + 28;
+        let mut corrupt = index.clone();
+        corrupt[len_offset..len_offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(&index_path, &corrupt).unwrap();
+        assert_eq!(read_index(&index_path, &key).err().unwrap().kind(), io::ErrorKind::InvalidData);
+
+        // And a file count, right after the three string maps
+        let u64_at = |offset: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&index[offset..offset + 8]);
+            u64::from_le_bytes(bytes) as usize
+        };
+        let data_len = u64_at(len_offset);
+        let mut count_offset = len_offset + 8 + data_len;
+        for _ in 0..3 {
+            count_offset += 8 + u64_at(count_offset) * 16;
+        }
+        let mut corrupt = index.clone();
+        corrupt[count_offset..count_offset + 8].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+        std::fs::write(&index_path, &corrupt).unwrap();
+        assert_eq!(read_index(&index_path, &key).err().unwrap().kind(), io::ErrorKind::InvalidData);
+
+        std::fs::write(&index_path, &index).unwrap();
+        assert!(read_index(&index_path, &key).is_ok());
+
+        std::fs::remove_file(index_path).unwrap();
+        std::fs::remove_file(arc_path).unwrap();
+    }
+
+    #[test]
+    fn stale_key() {
+        let arc_path = fixture::sample().write("stale-cache");
+        let arc = fixture::sample().open_path(&arc_path);
+        let key = CacheKey::new(&arc_path, &arc.tables.arc_header).unwrap();
+        let index_path = arc_path.with_extension("idx");
+        write_index(&index_path, &key, &arc).unwrap();
+        assert!(read_index(&index_path, &key).is_ok());
+
+        // Any change to the arc makes the index stale
+        let stale = CacheKey { size: key.size + 1, ..key };
+        let err = read_index(&index_path, &stale).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let stale = CacheKey { header_hash: !key.header_hash, ..key };
+        assert!(read_index(&index_path, &stale).is_err());
+
+        std::fs::remove_file(index_path).unwrap();
+        std::fs::remove_file(arc_path).unwrap();
+    }
+}
@@ -0,0 +1,367 @@
+use super::*;
+use serde::{Serialize, Serializer};
+use util::serialize_hash40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// How a file or stream is stored in one of the arcs being compared
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct FileVersion {
+    pub comp_size: u64,
+    pub decomp_size: u64,
+    /// `SubFileInfo.flags` for files, `StreamEntry.flags` for streams
+    pub flags: u32,
+    /// crc32 of the decompressed data
+    #[serde(serialize_with = "serialize_crc")]
+    pub content_hash: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileChange {
+    pub change: Change,
+    #[serde(serialize_with = "serialize_hash40")]
+    pub hash40: u64,
+    pub path: Option<ArcStr>,
+    /// Which of the `FileInfo2` rows sharing the path this is, for files with a row per region
+    pub region: Option<usize>,
+    pub old: Option<FileVersion>,
+    pub new: Option<FileVersion>,
+    /// Where the new version is in the new arc, streams without a label aren't in `Arc::files`
+    #[serde(skip)]
+    data: Option<DataInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DirectoryChange {
+    pub change: Change,
+    #[serde(serialize_with = "serialize_hash40")]
+    pub hash40: u64,
+    pub path: Option<ArcStr>,
+    /// Files and child directories, as paths or hashes when they aren't labeled
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// Everything that differs between two arcs, sorted by hash
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ArcDiff {
+    pub files: Vec<FileChange>,
+    pub directories: Vec<DirectoryChange>,
+    pub streams: Vec<FileChange>,
+}
+
+impl FileChange {
+    /// Path of the file, or its hash when it isn't labeled, followed by `@<region>` for files
+    /// with a row per region
+    pub fn display_path(&self) -> String {
+        let path = match self.path {
+            Some(path) => path.to_owned(),
+            None => format!("0x{:010x}", self.hash40),
+        };
+        match self.region {
+            Some(region) => format!("{}@{}", path, region),
+            None => path,
+        }
+    }
+}
+
+fn serialize_crc<S: Serializer>(crc: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:08x}", crc))
+}
+
+/// hash40 of a file or stream, with the region of the row for files with a row per region
+type DataKey = (u64, Option<usize>);
+
+/// Location of the data of a file in the arc
+#[derive(Debug, Clone, Copy)]
+struct DataInfo {
+    data: FileSlice<u8>,
+    decomp_size: u64,
+    flags: u32,
+    compression: Compression,
+}
+
+impl Arc {
+    /// The bytes of `data` in this arc's map
+    pub fn slice(&self, data: FileSlice<u8>) -> &[u8] {
+        &self.map[data.inner_ptr()..data.inner_ptr() + data.len()]
+    }
+
+    /// Data of every `FileInfo2` row, by hash40 and region
+    fn file_data_infos(&self) -> HashMap<DataKey, DataInfo> {
+        let tables = &self.tables;
+        tables.file_infos_v2
+            .iter()
+            .zip(self.row_regions())
+            .map(|(file_info, region)| {
+                let hash40 = tables.file_info_paths[file_info.hash_index as usize].path.hash40();
+                let (data, decomp_size, flags) = Arc::get_file_compressed(tables, file_info);
+                let info = DataInfo { data, decomp_size, flags, compression: Compression::from_flags(flags) };
+                ((hash40, region), info)
+            })
+            .collect()
+    }
+
+    fn stream_data_infos(&self) -> HashMap<DataKey, DataInfo> {
+        let tables = &self.tables;
+        tables.stream_entries
+            .iter()
+            .map(|entry| {
+                let hash40 = entry.hash as u64 + ((entry.name_length as u64) << 32);
+                let offset_entry = tables.stream_offset_entries[
+                    tables.stream_file_indices[entry.index as usize] as usize
+                ];
+                let data = FileSlice::new(offset_entry.offset as usize, offset_entry.size as usize);
+                let info = DataInfo {
+                    data,
+                    decomp_size: offset_entry.size,
+                    flags: entry.flags,
+                    compression: Compression::None,
+                };
+                ((hash40, None), info)
+            })
+            .collect()
+    }
+
+    /// Hashes of the files and child directories of every directory
+    fn directory_contents(&self) -> HashMap<u64, HashSet<u64>> {
+        let tables = &self.tables;
+        tables.directories
+            .iter()
+            .map(|dir| {
+                let start = dir.file_name_start_index as usize;
+                let files = tables.file_infos_v2[start..start + dir.file_info_count as usize]
+                    .iter()
+                    .map(|file_info| tables.file_info_paths[file_info.hash_index as usize].path.hash40());
+                let start = dir.child_dir_start_index as usize;
+                let dirs = tables.folder_child_hashes[start..start + dir.child_dir_count as usize]
+                    .iter()
+                    .map(|child| child.hash40());
+
+                (dir.hash40(), files.chain(dirs).collect())
+            })
+            .collect()
+    }
+
+    fn file_version(&self, info: &DataInfo) -> io::Result<FileVersion> {
+        Ok(FileVersion {
+            comp_size: info.data.len() as u64,
+            decomp_size: info.decomp_size,
+            flags: info.flags,
+            content_hash: crc32(&decompress_data(self.slice(info.data), info.compression)?),
+        })
+    }
+
+    /// Decompressed data of the new version of `change`, this being the new arc of the diff
+    pub fn read_change(&self, change: &FileChange) -> io::Result<Vec<u8>> {
+        let info = change.data.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("0x{:010x} was removed", change.hash40))
+        })?;
+        Ok(decompress_data(self.slice(info.data), info.compression)?.into_owned())
+    }
+
+    /// Compare this arc to a newer version of it. Files whose stored data differs are only
+    /// reported as modified if their decompressed data differs too
+    pub fn diff(&self, new: &Arc) -> io::Result<ArcDiff> {
+        let directories = diff_directories(self, new);
+        let files = diff_data(self, new, &self.file_data_infos(), &new.file_data_infos())?;
+        let streams = diff_data(self, new, &self.stream_data_infos(), &new.stream_data_infos())?;
+
+        Ok(ArcDiff { files, directories, streams })
+    }
+}
+
+fn diff_data(
+    old: &Arc,
+    new: &Arc,
+    old_infos: &HashMap<DataKey, DataInfo>,
+    new_infos: &HashMap<DataKey, DataInfo>
+) -> io::Result<Vec<FileChange>> {
+    let mut keys: Vec<DataKey> = old_infos.keys().chain(new_infos.keys()).cloned().collect();
+    keys.sort();
+    keys.dedup();
+
+    let changes: io::Result<Vec<Option<FileChange>>> = keys
+        .par_iter()
+        .map(|&key| {
+            let (hash40, region) = key;
+            let (old_version, new_version, change) = match (old_infos.get(&key), new_infos.get(&key)) {
+                (Some(old_info), Some(new_info)) => {
+                    let unchanged = old_info.decomp_size == new_info.decomp_size &&
+                        old_info.flags == new_info.flags &&
+                        old.slice(old_info.data) == new.slice(new_info.data);
+                    if unchanged {
+                        return Ok(None)
+                    }
+                    let old_version = old.file_version(old_info)?;
+                    let new_version = new.file_version(new_info)?;
+                    if old_version.content_hash == new_version.content_hash &&
+                        old_version.decomp_size == new_version.decomp_size &&
+                        old_version.flags & !(SUB_FILE_COMPRESSED | SUB_FILE_ZSTD) ==
+                            new_version.flags & !(SUB_FILE_COMPRESSED | SUB_FILE_ZSTD)
+                    {
+                        // Only recompressed
+                        return Ok(None)
+                    }
+                    (Some(old_version), Some(new_version), Change::Modified)
+                }
+                (Some(old_info), None) => (Some(old.file_version(old_info)?), None, Change::Removed),
+                (None, Some(new_info)) => (None, Some(new.file_version(new_info)?), Change::Added),
+                (None, None) => unreachable!(),
+            };
+
+            Ok(Some(FileChange {
+                change,
+                hash40,
+                path: new.get_name(hash40).or_else(|| old.get_name(hash40)),
+                region,
+                old: old_version,
+                new: new_version,
+                data: new_infos.get(&key).copied(),
+            }))
+        })
+        .collect();
+
+    Ok(changes?.into_iter().flatten().collect())
+}
+
+fn diff_directories(old: &Arc, new: &Arc) -> Vec<DirectoryChange> {
+    let old_dirs = old.directory_contents();
+    let new_dirs = new.directory_contents();
+    let mut hashes: Vec<u64> = old_dirs.keys().chain(new_dirs.keys()).cloned().collect();
+    hashes.sort();
+    hashes.dedup();
+
+    let empty = HashSet::new();
+    hashes
+        .into_iter()
+        .filter_map(|hash40| {
+            let change = match (old_dirs.contains_key(&hash40), new_dirs.contains_key(&hash40)) {
+                (true, true) => Change::Modified,
+                (true, false) => Change::Removed,
+                _ => Change::Added,
+            };
+            let old_children = old_dirs.get(&hash40).unwrap_or(&empty);
+            let new_children = new_dirs.get(&hash40).unwrap_or(&empty);
+            let mut added: Vec<String> = new_children
+                .difference(old_children)
+                .map(|&child| new.display_path(child))
+                .collect();
+            let mut removed: Vec<String> = old_children
+                .difference(new_children)
+                .map(|&child| old.display_path(child))
+                .collect();
+            if change == Change::Modified && added.is_empty() && removed.is_empty() {
+                return None
+            }
+            added.sort();
+            removed.sort();
+
+            Some(DirectoryChange {
+                change,
+                hash40,
+                path: new.get_name(hash40).or_else(|| old.get_name(hash40)),
+                added,
+                removed,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn diff_arcs() {
+        let old = fixture::sample()
+            .stream("stream:/sound/bgm/bgm_luigi.nus3audio", b"luigi bgm")
+            .open("diff-old");
+        let new = fixture::TestArc::new()
+            .file("fighter/mario/model.numdlb", &b"new mario model ".repeat(16))
+            .file("fighter/luigi/model.numdlb", &b"luigi model ".repeat(16))
+            .file("fighter/peach/model.numdlb", b"peach model")
+            .file("ui/param/ui_chara_db.prc", b"chara db")
+            .stream("stream:/sound/bgm/bgm_mario.nus3audio", b"new mario bgm")
+            .stream("stream:/sound/bgm/bgm_peach.nus3audio", b"peach bgm")
+            .open("diff-new");
+        let diff = old.diff(&new).unwrap();
+
+        let changes = |changes: &[FileChange]| {
+            let mut changes: Vec<_> = changes.iter().map(|change| (change.path.unwrap(), change.change)).collect();
+            changes.sort_by_key(|&(path, _)| path);
+            changes
+        };
+        assert_eq!(changes(&diff.files), vec![
+            ("fighter/mario/body.nutexb", Change::Removed),
+            ("fighter/mario/model.numdlb", Change::Modified),
+            ("fighter/peach/model.numdlb", Change::Added),
+        ]);
+        assert_eq!(changes(&diff.streams), vec![
+            ("stream:/sound/bgm/bgm_luigi.nus3audio", Change::Removed),
+            ("stream:/sound/bgm/bgm_mario.nus3audio", Change::Modified),
+            ("stream:/sound/bgm/bgm_peach.nus3audio", Change::Added),
+        ]);
+
+        let model = diff.files.iter().find(|change| change.change == Change::Modified).unwrap();
+        assert_eq!(model.old.unwrap().decomp_size, 12 * 16);
+        assert_eq!(model.new.unwrap().decomp_size, 16 * 16);
+        assert_eq!(model.new.unwrap().content_hash, crc32(&b"new mario model ".repeat(16)));
+
+        let mut directories: Vec<_> = diff.directories
+            .iter()
+            .map(|dir| (dir.path.unwrap(), dir.change, dir.added.clone(), dir.removed.clone()))
+            .collect();
+        directories.sort_by_key(|dir| dir.0);
+        assert_eq!(directories, vec![
+            ("fighter", Change::Modified, vec![String::from("fighter/peach")], vec![]),
+            ("fighter/mario", Change::Modified, vec![], vec![String::from("fighter/mario/body.nutexb")]),
+            ("fighter/peach", Change::Added, vec![String::from("fighter/peach/model.numdlb")], vec![]),
+        ]);
+
+        assert!(old.diff(&old).unwrap().files.is_empty());
+    }
+
+    #[test]
+    fn diff_regions() {
+        let old = fixture::sample()
+            .regional_file("ui/param/ui_msg.msbt", &[b"us message", b"jp message", b"eu message"])
+            .open("diff-regions-old");
+        let new = fixture::sample()
+            .regional_file("ui/param/ui_msg.msbt", &[b"us message", b"new jp message", b"eu message"])
+            .open("diff-regions-new");
+        let diff = old.diff(&new).unwrap();
+
+        let changes: Vec<_> = diff.files.iter().map(|change| (change.display_path(), change.change)).collect();
+        assert_eq!(changes, vec![(String::from("ui/param/ui_msg.msbt@1"), Change::Modified)]);
+        let change = &diff.files[0];
+        assert_eq!(change.old.unwrap().content_hash, crc32(b"jp message"));
+        assert_eq!(new.read_change(change).unwrap(), b"new jp message");
+        assert!(diff.directories.is_empty());
+    }
+
+    #[test]
+    fn serialize_hashes() {
+        let change = FileChange {
+            change: Change::Modified,
+            hash40: 0x12_3456_789a,
+            path: Some("fighter/mario/model.numdlb"),
+            region: None,
+            old: Some(FileVersion { comp_size: 4, decomp_size: 8, flags: 3, content_hash: 0xabc }),
+            new: None,
+            data: None,
+        };
+
+        let json = serde_json::to_value(&change).unwrap();
+        assert_eq!(json["change"], "modified");
+        assert_eq!(json["hash40"], "0x123456789a");
+        assert_eq!(json["old"]["content_hash"], "00000abc");
+        assert!(json["new"].is_null());
+    }
+}
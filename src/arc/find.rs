@@ -0,0 +1,181 @@
+use super::*;
+use globset::{GlobBuilder, GlobMatcher};
+use regex::Regex;
+
+/// What full paths are matched against. Unlabeled entries are named by their hash, the same
+/// way as in the mount, so patterns like `fighter/mario/**/0x*` find them too
+pub enum Pattern {
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// `*` and `?` stay within a directory, only `**` matches across them
+    pub fn glob(pattern: &str) -> io::Result<Self> {
+        GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map(|glob| Pattern::Glob(glob.compile_matcher()))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))
+    }
+
+    pub fn regex(pattern: &str) -> io::Result<Self> {
+        Regex::new(pattern)
+            .map(Pattern::Regex)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        match self {
+            Pattern::Glob(glob) => glob.is_match(path),
+            Pattern::Regex(regex) => regex.is_match(path),
+        }
+    }
+}
+
+/// Filters for `Arc::find`, every filter that is set has to match
+#[derive(Default)]
+pub struct Query {
+    pub pattern: Option<Pattern>,
+    /// Extension without the dot. Compared by hash, so it works for unlabeled files as long
+    /// as the extension is known
+    pub extension: Option<String>,
+    /// Decompressed size range, inclusive
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub compressed: Option<bool>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Found {
+    pub hash40: u64,
+    pub path: String,
+    pub comp_size: u64,
+    pub decomp_size: u64,
+    pub compressed: bool,
+}
+
+impl Arc {
+    /// Files in the tree matching `query`, sorted by path. Only the loaded part of the tree
+    /// is searched for arcs opened with `open_lazy`
+    pub fn find(&self, query: &Query) -> Vec<Found> {
+        let extension = query.extension.as_ref().map(|ext| hash40(ext.trim_start_matches('.')));
+        let extensions: HashMap<u64, u64> = match extension {
+            Some(_) => self.tables.file_info_paths
+                .iter()
+                .map(|path| (path.path.hash40(), path.ext.hash40()))
+                .collect(),
+            None => HashMap::new(),
+        };
+
+        let mut found = Vec::new();
+        let mut stack = vec![(0, String::new())];
+        while let Some((dir, dir_path)) = stack.pop() {
+            for &child in self.dir_children.get(&dir).into_iter().flatten() {
+                let stem = self.stems.get(&child).cloned().unwrap_or("");
+                let path = if dir_path.is_empty() {
+                    stem.to_owned()
+                } else {
+                    format!("{}/{}", dir_path, stem)
+                };
+                let (comp_size, decomp_size, compressed) = match self.files.get(&child) {
+                    Some(ArcFileInfo::Directory) => {
+                        stack.push((child, path));
+                        continue
+                    }
+                    Some(&ArcFileInfo::Compressed { data, decomp_size, flags }) => {
+                        (data.len() as u64, decomp_size, Compression::from_flags(flags) != Compression::None)
+                    }
+                    Some(&ArcFileInfo::Uncompressed { data, .. }) => (data.len() as u64, data.len() as u64, false),
+                    _ => continue,
+                };
+
+                if let Some(extension) = extension {
+                    let matches = match extensions.get(&child) {
+                        Some(&ext) => ext == extension,
+                        // Stream files aren't in file_info_paths and always have labels
+                        None => path.rsplit('/').next()
+                            .and_then(|name| name.rsplit_once('.'))
+                            .is_some_and(|(_, ext)| hash40(ext) == extension),
+                    };
+                    if !matches {
+                        continue
+                    }
+                }
+                if query.min_size.is_some_and(|min| decomp_size < min) ||
+                    query.max_size.is_some_and(|max| decomp_size > max) ||
+                    query.compressed.is_some_and(|filter| filter != compressed) ||
+                    query.pattern.as_ref().is_some_and(|pattern| !pattern.is_match(&path))
+                {
+                    continue
+                }
+
+                found.push(Found { hash40: child, path, comp_size, decomp_size, compressed });
+            }
+        }
+        found.sort_by(|a, b| a.path.cmp(&b.path));
+
+        found
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn find_files() {
+        let arc = fixture::sample().file("fighter/mario/model/body.nutexb", b"nested texture").open("find");
+        let find = |query: Query| arc.find(&query).into_iter().map(|found| found.path).collect::<Vec<_>>();
+        let glob = |pattern| Query { pattern: Some(Pattern::glob(pattern).unwrap()), ..Default::default() };
+
+        assert_eq!(find(Query::default()), vec![
+            "fighter/luigi/model.numdlb",
+            "fighter/mario/body.nutexb",
+            "fighter/mario/model.numdlb",
+            "fighter/mario/model/body.nutexb",
+            "stream:/sound/bgm/bgm_mario.nus3audio",
+            "ui/param/ui_chara_db.prc",
+        ]);
+        // `*` doesn't cross directories, `**` does
+        assert_eq!(find(glob("fighter/mario/*")), vec![
+            "fighter/mario/body.nutexb",
+            "fighter/mario/model.numdlb",
+        ]);
+        assert_eq!(find(glob("fighter/*.nutexb")), Vec::<String>::new());
+        assert_eq!(find(glob("fighter/**/*.nutexb")), vec![
+            "fighter/mario/body.nutexb",
+            "fighter/mario/model/body.nutexb",
+        ]);
+        assert_eq!(find(Query { extension: Some(String::from(".numdlb")), ..Default::default() }), vec![
+            "fighter/luigi/model.numdlb",
+            "fighter/mario/model.numdlb",
+        ]);
+        assert_eq!(find(Query { extension: Some(String::from("nus3audio")), ..Default::default() }), vec![
+            "stream:/sound/bgm/bgm_mario.nus3audio",
+        ]);
+        assert_eq!(find(Query { min_size: Some(9), max_size: Some(13), ..Default::default() }), vec![
+            "fighter/mario/body.nutexb",
+            "stream:/sound/bgm/bgm_mario.nus3audio",
+        ]);
+
+        let compressed = arc.find(&Query { compressed: Some(true), ..Default::default() });
+        assert_eq!(compressed.len(), 2);
+        assert!(compressed.iter().all(|found| found.decomp_size == 12 * 16 && found.comp_size < found.decomp_size));
+    }
+
+    #[test]
+    fn patterns() {
+        let glob = Pattern::glob("fighter/mario/**/model.numdlb").unwrap();
+        assert!(glob.is_match("fighter/mario/model/body/c00/model.numdlb"));
+        assert!(!glob.is_match("fighter/luigi/model/body/c00/model.numdlb"));
+
+        let unlabeled = Pattern::glob("fighter/mario/**/0x*").unwrap();
+        assert!(unlabeled.is_match("fighter/mario/model/0x12a4bf9e07"));
+
+        let regex = Pattern::regex(r"^fighter/\w+/model/body/c0[0-7]/").unwrap();
+        assert!(regex.is_match("fighter/mario/model/body/c07/model.numdlb"));
+        assert!(!regex.is_match("fighter/mario/model/body/c08/model.numdlb"));
+        assert!(Pattern::regex("(").is_err());
+    }
+}
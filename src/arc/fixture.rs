@@ -0,0 +1,432 @@
+//! Small arcs built in memory for tests, laid out the way `Arc::parse` reads them
+
+use super::*;
+use std::collections::BTreeSet;
+use std::mem::size_of;
+use std::path::PathBuf;
+use writer::{as_bytes, slice_as_bytes};
+
+/// Buckets of the path hash to index table
+const BUCKET_COUNT: usize = 3;
+
+const MAGIC: u64 = 0xABCDEF9876543210;
+
+/// Where the stream data starts, after the header
+const STREAM_SECTION: usize = 0x100;
+
+/// An arc with a directory for every parent of its files. Every directory has its own data in
+/// the file section, the last one being the shared section
+#[derive(Default)]
+pub struct TestArc {
+    /// Path of every file with its data, then the data of its regional variants
+    files: Vec<(&'static str, Vec<Vec<u8>>)>,
+    streams: Vec<(&'static str, Vec<u8>)>,
+    /// Files and streams whose paths and names aren't labeled
+    unlabeled: HashSet<&'static str>,
+}
+
+/// Parent path and name of `path`
+fn split(path: &str) -> (&str, &str) {
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+fn group(hash40: u64, index: usize) -> HashIndexGroup {
+    HashIndexGroup::new(hash40, index)
+}
+
+fn hash(string: &str) -> Hash40 {
+    Hash40::new(hash40(string))
+}
+
+fn push<T: Copy>(out: &mut Vec<u8>, values: &[T]) {
+    out.extend_from_slice(slice_as_bytes(values));
+}
+
+fn align(out: &mut Vec<u8>) {
+    out.resize(out.len().div_ceil(0x10) * 0x10, 0);
+}
+
+impl TestArc {
+    pub fn new() -> Self {
+        TestArc::default()
+    }
+
+    pub fn file(mut self, path: &'static str, data: &[u8]) -> Self {
+        self.files.push((path, vec![data.to_vec()]));
+        self
+    }
+
+    /// A file with a `FileInfo2` row for each entry of `data`, all sharing its path
+    pub fn regional_file(mut self, path: &'static str, data: &[&[u8]]) -> Self {
+        self.files.push((path, data.iter().map(|data| data.to_vec()).collect()));
+        self
+    }
+
+    pub fn stream(mut self, path: &'static str, data: &[u8]) -> Self {
+        self.streams.push((path, data.to_vec()));
+        self
+    }
+
+    pub fn unlabeled(mut self, path: &'static str) -> Self {
+        self.unlabeled.insert(path);
+        self
+    }
+
+    fn dirs(&self) -> Vec<&'static str> {
+        let mut dirs = BTreeSet::new();
+        for &(path, _) in &self.files {
+            let mut parent = split(path).0;
+            while !parent.is_empty() {
+                dirs.insert(parent);
+                parent = split(parent).0;
+            }
+        }
+
+        // Sorted, so parents come before their children
+        dirs.into_iter().collect()
+    }
+
+    /// Labels of every path, directory, name and extension, apart from the unlabeled ones
+    pub fn labels(&self) -> HashMap<u64, ArcStr> {
+        let mut labels = HashMap::new();
+        for &(path, _) in &self.files {
+            if self.unlabeled.contains(path) {
+                continue
+            }
+            let (_, name) = split(path);
+            labels.insert(hash40(path), path);
+            labels.insert(hash40(name), name);
+            if let Some((_, ext)) = name.rsplit_once('.') {
+                labels.insert(hash40(ext), ext);
+            }
+        }
+        for dir in self.dirs() {
+            labels.insert(hash40(dir), dir);
+            labels.insert(hash40(split(dir).1), split(dir).1);
+        }
+        for &(path, _) in self.streams.iter().filter(|(path, _)| !self.unlabeled.contains(path)) {
+            labels.insert(hash40(path), path);
+        }
+
+        labels
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let dirs = self.dirs();
+        let dir_index: HashMap<&str, usize> = dirs.iter().enumerate().map(|(i, &dir)| (dir, i)).collect();
+        let folder = |path: &str| dir_index[split(path).0];
+
+        // FileInfo2 rows: the files of each directory, then the regional variants
+        let mut rows: Vec<(usize, &[u8])> = Vec::new();
+        let mut main_rows = vec![0; self.files.len()];
+        let mut dir_files = vec![(0, 0); dirs.len()];
+        for (d, &dir) in dirs.iter().enumerate() {
+            dir_files[d].0 = rows.len();
+            for (i, (_, data)) in self.files.iter().enumerate().filter(|(_, (path, _))| split(path).0 == dir) {
+                main_rows[i] = rows.len();
+                rows.push((i, &data[0]));
+            }
+            dir_files[d].1 = rows.len() - dir_files[d].0;
+        }
+        for (i, (_, data)) in self.files.iter().enumerate() {
+            for data in &data[1..] {
+                rows.push((i, data));
+            }
+        }
+
+        // Data of each directory, sub file offsets are relative to the directory
+        let mut sub_files = vec![SubFileInfo { offset: 0, comp_size: 0, decomp_size: 0, flags: 0 }; rows.len()];
+        let mut folder_data = vec![Vec::new(); dirs.len()];
+        for (r, &(file, data)) in rows.iter().enumerate() {
+            let blob = &mut folder_data[folder(self.files[file].0)];
+            let compressed = zstd::encode_all(data, 0).unwrap();
+            let (stored, flags) = if compressed.len() < data.len() {
+                (compressed, SUB_FILE_COMPRESSED | SUB_FILE_ZSTD)
+            } else {
+                (data.to_vec(), 0)
+            };
+            align(blob);
+            sub_files[r] = SubFileInfo {
+                offset: (blob.len() >> 2) as u32,
+                comp_size: stored.len() as u32,
+                decomp_size: data.len() as u32,
+                flags,
+            };
+            blob.extend_from_slice(&stored);
+        }
+
+        let mut out = vec![0; STREAM_SECTION];
+        let mut stream_offset_entries = Vec::new();
+        for (_, data) in &self.streams {
+            align(&mut out);
+            stream_offset_entries.push(StreamOffsetEntry { size: data.len() as u64, offset: out.len() as u64 });
+            out.extend_from_slice(data);
+        }
+
+        align(&mut out);
+        let file_section_offset = out.len();
+        let mut folder_offsets = Vec::new();
+        for (d, blob) in folder_data.iter().enumerate() {
+            align(&mut out);
+            let (start, count) = dir_files[d];
+            let decomp_size = rows
+                .iter()
+                .filter(|(file, _)| folder(self.files[*file].0) == d)
+                .map(|(_, data)| data.len() as u32)
+                .sum();
+            folder_offsets.push(DirectoryOffsets {
+                offset: (out.len() - file_section_offset) as u64,
+                decomp_size,
+                size: blob.len() as u32,
+                sub_data_start_index: start as u32,
+                sub_data_count: count as u32,
+                resource_index: 0,
+            });
+            out.extend_from_slice(blob);
+        }
+        let shared_section_offset = file_section_offset + folder_offsets.last().map_or(0, |folder| folder.offset as usize);
+
+        let table = self.table(&dirs, &rows, &main_rows, &dir_files, &stream_offset_entries, &folder_offsets, &sub_files);
+        let compressed_table = zstd::encode_all(&table[..], 0).unwrap();
+        align(&mut out);
+        let file_system = out.len();
+        push(&mut out, &[CompTableHeader {
+            header_size: 0x10,
+            decomp_size: table.len() as u32,
+            comp_size: compressed_table.len() as u32,
+            section_size: compressed_table.len() as u32,
+        }]);
+        out.extend_from_slice(&compressed_table);
+
+        let unk_data = zstd::encode_all(&b"unk section"[..], 0).unwrap();
+        align(&mut out);
+        let unk_section = out.len();
+        push(&mut out, &[CompTableHeader {
+            header_size: 0x10,
+            decomp_size: 11,
+            comp_size: unk_data.len() as u32,
+            section_size: unk_data.len() as u32,
+        }]);
+        out.extend_from_slice(&unk_data);
+
+        let header = ArcHeader {
+            magic: MAGIC,
+            music_section_offset: STREAM_SECTION as u64,
+            file_section_offset: file_section_offset as u64,
+            shared_section_offset: shared_section_offset as u64,
+            file_system: FilePtr64::new(file_system as u64),
+            unk_section_offset: FilePtr64::new(unk_section as u64),
+        };
+        out[..size_of::<ArcHeader>()].copy_from_slice(as_bytes(&header));
+
+        out
+    }
+
+    /// The decompressed file system table
+    #[allow(clippy::too_many_arguments)]
+    fn table(
+        &self,
+        dirs: &[&'static str],
+        rows: &[(usize, &[u8])],
+        main_rows: &[usize],
+        dir_files: &[(usize, usize)],
+        stream_offset_entries: &[StreamOffsetEntry],
+        folder_offsets: &[DirectoryOffsets],
+        sub_files: &[SubFileInfo],
+    ) -> Vec<u8> {
+        let mut table = vec![0; 0x100];
+        let streams = self.streams.len() as u32;
+        push(&mut table, &[StreamHeader {
+            quick_dir_count: 0,
+            stream_hash_count: streams,
+            stream_file_index_count: streams,
+            stream_offset_entry_count: streams,
+        }]);
+        let stream_hashes: Vec<u64> = self.streams.iter().map(|(path, _)| hash40(path)).collect();
+        push(&mut table, &stream_hashes);
+        for (i, &hash40) in stream_hashes.iter().enumerate() {
+            let entry = StreamEntry { hash: hash40 as u32, name_length: (hash40 >> 32) as u8, index: i as u32, flags: 0 };
+            table.extend_from_slice(&entry.pack());
+        }
+        push(&mut table, &(0..streams).collect::<Vec<u32>>());
+        push(&mut table, stream_offset_entries);
+
+        // Paths are grouped by the hash40 modulo the bucket count, then sorted by hash40
+        let mut path_hash_to_index = Vec::new();
+        let mut buckets = Vec::new();
+        for bucket in 0..BUCKET_COUNT as u64 {
+            let mut members: Vec<_> = self.files
+                .iter()
+                .enumerate()
+                .map(|(i, (path, _))| group(hash40(path), i))
+                .filter(|group| group.hash40() % BUCKET_COUNT as u64 == bucket)
+                .collect();
+            members.sort_by_key(HashIndexGroup::hash40);
+            buckets.push(FileInfoBucket { start: path_hash_to_index.len() as u32, count: members.len() as u32 });
+            path_hash_to_index.extend(members);
+        }
+        push(&mut table, &[FilePathLookupHeader {
+            path_hash_count: path_hash_to_index.len() as u32,
+            bucket_count: BUCKET_COUNT as u32,
+        }]);
+        push(&mut table, &buckets);
+        push(&mut table, &path_hash_to_index);
+
+        let dir_index = |path: &str| dirs.iter().position(|&dir| dir == split(path).0).unwrap();
+        let file_info_paths: Vec<_> = self.files
+            .iter()
+            .enumerate()
+            .map(|(i, &(path, _))| {
+                let (parent, name) = split(path);
+                let ext = name.rsplit_once('.').map_or("", |(_, ext)| ext);
+                FileInformationPath {
+                    path: group(hash40(path), i),
+                    ext: group(hash40(ext), 0),
+                    parent: group(hash40(parent), 0),
+                    file_name: group(hash40(name), 0),
+                }
+            })
+            .collect();
+        push(&mut table, &file_info_paths);
+        let file_info_indices: Vec<_> = self.files
+            .iter()
+            .enumerate()
+            .map(|(i, (path, _))| FileInformationIndex {
+                dir_offset_index: dir_index(path) as u32,
+                file_info_index: main_rows[i] as u32,
+            })
+            .collect();
+        push(&mut table, &file_info_indices);
+
+        let mut dir_hash_to_index: Vec<_> = dirs.iter().enumerate().map(|(d, dir)| group(hash40(dir), d)).collect();
+        dir_hash_to_index.sort_by_key(HashIndexGroup::hash40);
+        push(&mut table, &dir_hash_to_index);
+
+        let mut folder_child_hashes = Vec::new();
+        let mut directories = Vec::new();
+        for (d, &dir) in dirs.iter().enumerate() {
+            let (parent, name) = split(dir);
+            let children: Vec<_> = dirs
+                .iter()
+                .enumerate()
+                .filter(|(_, child)| split(child).0 == dir)
+                .map(|(c, child)| group(hash40(child), c))
+                .collect();
+            directories.push(DirectoryInfo {
+                path_hash: crc32(dir.as_bytes()),
+                dir_offset_index: ((d as u32) << 8) | dir.len() as u32,
+                name: hash(name),
+                parent: hash(parent),
+                extra_dis_re: hash(""),
+                file_name_start_index: dir_files[d].0 as u32,
+                file_info_count: dir_files[d].1 as u32,
+                child_dir_start_index: folder_child_hashes.len() as u32,
+                child_dir_count: children.len() as u32,
+                flags: 0,
+            });
+            folder_child_hashes.extend(children);
+        }
+        push(&mut table, &directories);
+        push(&mut table, folder_offsets);
+        push(&mut table, &folder_child_hashes);
+
+        let file_infos: Vec<_> = rows
+            .iter()
+            .enumerate()
+            .map(|(r, &(file, _))| FileInfo2 {
+                hash_index: file as u32,
+                hash_index_2: file as u32,
+                sub_file_index: r as u32,
+                flags: 0,
+            })
+            .collect();
+        push(&mut table, &file_infos);
+        let sub_index: Vec<_> = rows
+            .iter()
+            .enumerate()
+            .map(|(r, &(file, _))| FileInfoSubIndex {
+                folder_offset_index: dir_index(self.files[file].0) as u32,
+                sub_file_index: r as u32,
+                file_info_index_and_flag: r as u32,
+            })
+            .collect();
+        push(&mut table, &sub_index);
+        push(&mut table, sub_files);
+
+        let header = FileSystemHeader {
+            table_filesize: table.len() as u32,
+            file_info_path_count: self.files.len() as u32,
+            file_info_index_count: self.files.len() as u32,
+            folder_count: dirs.len() as u32,
+            folder_offset_count_1: folder_offsets.len() as u32,
+            hash_folder_count: folder_child_hashes.len() as u32,
+            file_info_count: rows.len() as u32,
+            file_info_sub_index_count: rows.len() as u32,
+            sub_file_count: rows.len() as u32,
+            folder_offset_count_2: 0,
+            sub_file_count_2: 0,
+            padding: 0,
+            unk1_10: 0x10,
+            unk2_10: 0x10,
+            regional_count_1: 0,
+            regional_count_2: 0,
+            padding2: 0,
+            version: 0x000d_0000,
+            extra_folder: 0,
+            extra_count: 0,
+            unk: [0; 2],
+            extra_count_2: 0,
+            extra_sub_count: 0,
+        };
+        table[..size_of::<FileSystemHeader>()].copy_from_slice(as_bytes(&header));
+
+        table
+    }
+
+    /// Write the arc to a temporary file named after `name`
+    pub fn write(&self, name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("arc-fuse-{}-{}.arc", std::process::id(), name));
+        std::fs::write(&path, self.build()).unwrap();
+        path
+    }
+
+    /// Parse `data` with this arc's labels and the whole tree loaded, like `Arc::open`
+    pub fn open_bytes(&self, data: &[u8], name: &str) -> Arc {
+        let path = std::env::temp_dir().join(format!("arc-fuse-{}-{}.arc", std::process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        let arc = self.open_path(&path);
+        std::fs::remove_file(path).unwrap();
+        arc
+    }
+
+    pub fn open_path(&self, path: &Path) -> Arc {
+        let mut arc = Arc::parse(path).unwrap();
+        arc.names.extend(self.labels());
+        arc.load_stream_files();
+        arc.load_directories();
+        arc
+    }
+
+    pub fn open(&self, name: &str) -> Arc {
+        self.open_bytes(&self.build(), name)
+    }
+
+    pub fn open_lazy(&self, name: &str) -> Arc {
+        let path = self.write(name);
+        let mut arc = Arc::open_lazy(&path).unwrap();
+        arc.names.extend(self.labels());
+        std::fs::remove_file(path).unwrap();
+        arc
+    }
+}
+
+/// Fighter files in one directory and a UI file in another, the last directory (`ui/param`)
+/// being the shared section
+pub fn sample() -> TestArc {
+    TestArc::new()
+        .file("fighter/mario/model.numdlb", &b"mario model ".repeat(16))
+        .file("fighter/mario/body.nutexb", b"mario texture")
+        .file("fighter/luigi/model.numdlb", &b"luigi model ".repeat(16))
+        .file("ui/param/ui_chara_db.prc", b"chara db")
+        .stream("stream:/sound/bgm/bgm_mario.nus3audio", b"mario bgm")
+}
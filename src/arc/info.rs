@@ -0,0 +1,116 @@
+use super::*;
+use cached::Cached;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fmt::Write;
+
+/// Counters of the cache of decompressed files
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct CacheStats {
+    pub hits: u32,
+    pub misses: u32,
+    pub size: usize,
+    pub capacity: usize,
+}
+
+pub fn cache_stats() -> CacheStats {
+    let cache = FILE_CACHE.lock().unwrap();
+    CacheStats {
+        hits: cache.cache_hits().unwrap_or(0),
+        misses: cache.cache_misses().unwrap_or(0),
+        size: cache.cache_size(),
+        capacity: cache.cache_capacity().unwrap_or(0),
+    }
+}
+
+impl Arc {
+    /// `ArcHeader` and `FileSystemHeader`
+    pub fn header_json(&self) -> Value {
+        let header = &self.tables.arc_header;
+        // Packed, so the fields are copied out before taking references
+        let FileSystemHeader {
+            table_filesize, file_info_path_count, file_info_index_count, folder_count,
+            folder_offset_count_1, hash_folder_count, file_info_count, file_info_sub_index_count,
+            sub_file_count, folder_offset_count_2, sub_file_count_2, unk1_10, unk2_10,
+            regional_count_1, regional_count_2, version, extra_folder, extra_count, unk,
+            extra_count_2, extra_sub_count, ..
+        } = self.tables.fs_header;
+        json!({
+            "arc_header": {
+                "magic": format!("0x{:016x}", header.magic),
+                "music_section_offset": header.music_section_offset,
+                "file_section_offset": header.file_section_offset,
+                "shared_section_offset": header.shared_section_offset,
+                "file_system_offset": header.file_system.inner(),
+                "unk_section_offset": header.unk_section_offset.inner(),
+            },
+            "file_system_header": {
+                "table_filesize": table_filesize,
+                "file_info_path_count": file_info_path_count,
+                "file_info_index_count": file_info_index_count,
+                "folder_count": folder_count,
+                "folder_offset_count_1": folder_offset_count_1,
+                "hash_folder_count": hash_folder_count,
+                "file_info_count": file_info_count,
+                "file_info_sub_index_count": file_info_sub_index_count,
+                "sub_file_count": sub_file_count,
+                "folder_offset_count_2": folder_offset_count_2,
+                "sub_file_count_2": sub_file_count_2,
+                "unk1_10": unk1_10,
+                "unk2_10": unk2_10,
+                "regional_count_1": regional_count_1,
+                "regional_count_2": regional_count_2,
+                "version": format!("0x{:x}", version),
+                "extra_folder": extra_folder,
+                "extra_count": extra_count,
+                "unk": unk,
+                "extra_count_2": extra_count_2,
+                "extra_sub_count": extra_sub_count,
+            },
+        })
+    }
+
+    /// Table sizes, label coverage, cache counters and decompression failures
+    pub fn stats_json(&self) -> Value {
+        let tables = &self.tables;
+        let paths: HashSet<u64> = tables.file_info_paths.iter().map(|path| path.path.hash40()).collect();
+        let labeled_paths = paths.iter().filter(|hash40| self.names.contains_key(hash40)).count();
+        let labeled_dirs = tables.directories.iter().filter(|dir| self.names.contains_key(&dir.hash40())).count();
+        let labeled_streams = tables.stream_entries
+            .iter()
+            .filter(|entry| self.get_name(entry.hash as u64 + ((entry.name_length as u64) << 32)).is_some())
+            .count();
+
+        json!({
+            "files": paths.len(),
+            "directories": tables.directories.len(),
+            "streams": tables.stream_entries.len(),
+            "sub_files": tables.sub_files.len(),
+            "loaded_entries": self.files.len(),
+            "labels": {
+                "files": labeled_paths,
+                "directories": labeled_dirs,
+                "streams": labeled_streams,
+                "file_coverage": labeled_paths as f64 / usize::max(paths.len(), 1) as f64,
+            },
+            "cache": cache_stats(),
+            "decompression_failures": decompression_failures(),
+        })
+    }
+
+    /// The unlabeled files grouped like `unlabeled_files`, one hash per line
+    pub fn unknown_hashes_text(&self) -> String {
+        let mut text = String::new();
+        for group in self.unlabeled_files() {
+            let _ = writeln!(
+                text, "# {} {} ({} files)",
+                self.display_path(group.parent), self.display_path(group.ext), group.hashes.len()
+            );
+            for hash40 in group.hashes {
+                let _ = writeln!(text, "0x{:010x}", hash40);
+            }
+        }
+
+        text
+    }
+}
@@ -0,0 +1,209 @@
+use super::*;
+use std::fmt;
+
+/// Indices of the `FileInfo2`s a redirecting `FileInfo2` leads to, stopping at the first one
+/// that doesn't redirect or at a loop
+fn redirect_chain(file_infos: &[FileInfo2], file_info_indices: &[FileInformationIndex], start: usize) -> Vec<usize> {
+    let mut chain = Vec::new();
+    let mut file_info = file_infos[start];
+    while file_info.flags & REDIRECT != 0 {
+        let next = match file_info_indices.get(file_info.hash_index_2 as usize) {
+            Some(index) => index.file_info_index as usize,
+            None => break,
+        };
+        if next == start || chain.contains(&next) || next >= file_infos.len() {
+            break
+        }
+        chain.push(next);
+        file_info = file_infos[next];
+    }
+
+    chain
+}
+
+/// The rows used to read the data of one `FileInfo2` of a file
+pub struct FileInfoRows {
+    pub file_info: (usize, FileInfo2),
+    pub info_index: (usize, FileInformationIndex),
+    /// Every `FileInfo2` the file redirects to, in order
+    pub redirects: Vec<(usize, FileInfo2)>,
+    pub sub_index: (usize, FileInfoSubIndex),
+    pub sub_file: (usize, SubFileInfo),
+    pub folder_offset: (usize, DirectoryOffsets),
+}
+
+/// Every table row describing a file, directory or stream
+pub struct Inspection<'a> {
+    arc: &'a Arc,
+    pub hash40: u64,
+    pub path: Option<(usize, FileInformationPath)>,
+    pub file_infos: Vec<FileInfoRows>,
+    pub directory: Option<(usize, DirectoryInfo, Option<DirectoryOffsets>)>,
+    pub stream: Option<(StreamEntry, StreamOffsetEntry)>,
+}
+
+impl Arc {
+    /// Collect the table rows of the file, directory or stream `hash40`
+    pub fn inspect(&self, hash40: u64) -> io::Result<Inspection<'_>> {
+        let tables = &self.tables;
+        let path = self.file_path_index(hash40)
+            .and_then(|index| Some((index, *tables.file_info_paths.get(index)?)));
+
+        let file_infos = match path {
+            Some((path_index, _)) => tables.file_infos_v2
+                .iter()
+                .enumerate()
+                .filter(|(_, file_info)| file_info.hash_index as usize == path_index)
+                .map(|(index, &file_info)| {
+                    let info_index = file_info.hash_index_2 as usize;
+                    let redirects = redirect_chain(&tables.file_infos_v2, &tables.file_info_indices, index);
+                    let sub_index = Arc::get_sub_index(tables, &file_info);
+                    let sub_file_index = sub_index.sub_file_index as usize;
+                    let folder_offset_index = sub_index.folder_offset_index as usize;
+                    let data_info = redirects.first().map(|&index| tables.file_infos_v2[index]).unwrap_or(file_info);
+                    FileInfoRows {
+                        file_info: (index, file_info),
+                        info_index: (info_index, tables.file_info_indices[info_index]),
+                        redirects: redirects.iter().map(|&index| (index, tables.file_infos_v2[index])).collect(),
+                        sub_index: (data_info.sub_file_index as usize, sub_index),
+                        sub_file: (sub_file_index, tables.sub_files[sub_file_index]),
+                        folder_offset: (folder_offset_index, tables.folder_offsets[folder_offset_index]),
+                    }
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let directory = self.directory_index(hash40).and_then(|index| {
+            let dir = *tables.directories.get(index)?;
+            Some((index, dir, tables.folder_offsets.get((dir.dir_offset_index >> 8) as usize).copied()))
+        });
+
+        let stream = tables.stream_entries
+            .iter()
+            .find(|entry| entry.hash as u64 + ((entry.name_length as u64) << 32) == hash40)
+            .map(|&entry| {
+                let index = tables.stream_file_indices[entry.index as usize] as usize;
+                (entry, tables.stream_offset_entries[index])
+            });
+
+        if path.is_none() && directory.is_none() && stream.is_none() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("0x{:010x} isn't in the arc", hash40)))
+        }
+
+        Ok(Inspection {
+            arc: self,
+            hash40,
+            path,
+            file_infos,
+            directory,
+            stream,
+        })
+    }
+}
+
+impl Inspection<'_> {
+    fn name(&self, hash40: u64) -> String {
+        format!("0x{:010x} ({})", hash40, self.arc.display_path(hash40))
+    }
+}
+
+impl fmt::Display for Inspection<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.name(self.hash40))?;
+
+        if let Some((index, path)) = self.path {
+            writeln!(f, "FileInformationPath[{}]", index)?;
+            writeln!(f, "    path:      {}", self.name(path.path.hash40()))?;
+            writeln!(f, "    ext:       {}", self.name(path.ext.hash40()))?;
+            writeln!(f, "    parent:    {}", self.name(path.parent.hash40()))?;
+            writeln!(f, "    file_name: {}", self.name(path.file_name.hash40()))?;
+        }
+
+        for rows in &self.file_infos {
+            let (index, file_info) = rows.file_info;
+            writeln!(
+                f, "FileInfo2[{}] hash_index: {}, hash_index_2: {}, sub_file_index: {}, flags: 0x{:08x}",
+                index, file_info.hash_index, file_info.hash_index_2, file_info.sub_file_index, file_info.flags
+            )?;
+            let (index, info_index) = rows.info_index;
+            writeln!(
+                f, "    FileInformationIndex[{}] dir_offset_index: {}, file_info_index: {}",
+                index, info_index.dir_offset_index, info_index.file_info_index
+            )?;
+            for &(index, file_info) in &rows.redirects {
+                let path = self.arc.tables.file_info_paths[file_info.hash_index as usize].path.hash40();
+                writeln!(f, "    redirects to FileInfo2[{}] {}", index, self.name(path))?;
+            }
+            let (index, sub_index) = rows.sub_index;
+            writeln!(
+                f, "    FileInfoSubIndex[{}] folder_offset_index: {}, sub_file_index: {}, file_info_index_and_flag: 0x{:08x}",
+                index, sub_index.folder_offset_index, sub_index.sub_file_index, sub_index.file_info_index_and_flag
+            )?;
+            let (index, sub_file) = rows.sub_file;
+            writeln!(
+                f, "    SubFileInfo[{}] offset: 0x{:x}, comp_size: 0x{:x}, decomp_size: 0x{:x}, flags: 0x{:08x} ({:?})",
+                index, sub_file.offset, sub_file.comp_size, sub_file.decomp_size, sub_file.flags,
+                Compression::from_flags(sub_file.flags)
+            )?;
+            let (index, folder) = rows.folder_offset;
+            writeln!(f, "    DirectoryOffsets[{}] {}", index, DirectoryOffsetsRow(folder))?;
+        }
+
+        if let Some((index, dir, folder)) = self.directory {
+            writeln!(f, "DirectoryInfo[{}]", index)?;
+            writeln!(f, "    name:   {}", self.name(dir.name.hash40()))?;
+            writeln!(f, "    parent: {}", self.name(dir.parent.hash40()))?;
+            writeln!(f, "    dir_offset_index: {}, flags: 0x{:08x}", dir.dir_offset_index >> 8, dir.flags)?;
+            writeln!(f, "    files: {} from {}", dir.file_info_count, dir.file_name_start_index)?;
+            writeln!(f, "    child directories: {} from {}", dir.child_dir_count, dir.child_dir_start_index)?;
+            writeln!(f, "    extra_dis_re: {}", self.name(dir.extra_dis_re.hash40()))?;
+            if let Some(folder) = folder {
+                writeln!(f, "    DirectoryOffsets {}", DirectoryOffsetsRow(folder))?;
+            }
+        }
+
+        if let Some((entry, offset)) = self.stream {
+            writeln!(f, "StreamEntry index: {}, flags: 0x{:08x}", entry.index, entry.flags)?;
+            writeln!(f, "    StreamOffsetEntry offset: 0x{:x}, size: 0x{:x}", offset.offset, offset.size)?;
+        }
+
+        Ok(())
+    }
+}
+
+struct DirectoryOffsetsRow(DirectoryOffsets);
+
+impl fmt::Display for DirectoryOffsetsRow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Packed, so the fields are copied before formatting
+        let DirectoryOffsets { offset, decomp_size, size, sub_data_start_index, sub_data_count, resource_index } = self.0;
+        write!(
+            f, "offset: 0x{:x}, decomp_size: 0x{:x}, size: 0x{:x}, sub files: {} from {}, resource_index: {}",
+            offset, decomp_size, size, sub_data_count, sub_data_start_index, resource_index
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn file_info(hash_index_2: u32, flags: u32) -> FileInfo2 {
+        FileInfo2 { hash_index: 0, hash_index_2, sub_file_index: 0, flags }
+    }
+
+    fn info_index(file_info_index: u32) -> FileInformationIndex {
+        FileInformationIndex { dir_offset_index: 0, file_info_index }
+    }
+
+    #[test]
+    fn redirects() {
+        let file_infos = [file_info(0, REDIRECT), file_info(1, REDIRECT), file_info(2, 0), file_info(3, REDIRECT)];
+        let indices = [info_index(1), info_index(2), info_index(2), info_index(3)];
+        assert_eq!(redirect_chain(&file_infos, &indices, 0), vec![1, 2]);
+        assert_eq!(redirect_chain(&file_infos, &indices, 2), Vec::<usize>::new());
+        // Redirects to itself
+        assert_eq!(redirect_chain(&file_infos, &indices, 3), Vec::<usize>::new());
+    }
+}
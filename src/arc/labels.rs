@@ -0,0 +1,203 @@
+use super::*;
+use std::collections::BTreeMap;
+
+/// Shortest run of path characters worth hashing
+const MIN_STRING_LEN: usize = 4;
+
+fn is_path_char(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"_-./".contains(&byte)
+}
+
+/// Runs of printable path characters in `data` that look like a path or a file name
+fn path_strings(data: &[u8]) -> impl Iterator<Item = &str> {
+    data.split(|&byte| !is_path_char(byte))
+        .filter(|run| run.len() >= MIN_STRING_LEN && run.len() <= u8::MAX as usize)
+        .filter(|run| run.iter().any(|byte| byte.is_ascii_alphabetic()))
+        // Only path characters, so always valid utf8
+        .map(|run| std::str::from_utf8(run).unwrap().trim_matches(|c| c == '.' || c == '/'))
+}
+
+/// Every string `string` could be a label for: itself, its parent directories, its file
+/// name, and with a known extension added if it doesn't have one
+fn candidates<'a>(string: &'a str, extensions: &'a [ArcStr]) -> impl Iterator<Item = Cow<'a, str>> {
+    let directories = string.match_indices('/').map(move |(i, _)| &string[..i]);
+    let file_name = string.rsplit('/').next().filter(|name| name.len() != string.len());
+    let has_extension = file_name.unwrap_or(string).contains('.');
+    let with_extensions = extensions
+        .iter()
+        .filter(move |_| !has_extension)
+        .map(move |ext| Cow::Owned(format!("{}.{}", string, ext)));
+
+    std::iter::once(string)
+        .chain(directories)
+        .chain(file_name)
+        .map(Cow::Borrowed)
+        .chain(with_extensions)
+}
+
+/// Unlabeled files sharing a parent directory and an extension
+#[derive(Debug, Clone)]
+pub struct UnlabeledGroup {
+    pub parent: u64,
+    pub ext: u64,
+    pub hashes: Vec<u64>,
+}
+
+impl Arc {
+    /// Hashes used by `file_info_paths` and `directories` that don't have a label
+    pub fn unlabeled_hashes(&self) -> HashSet<u64> {
+        let tables = &self.tables;
+        let paths = tables.file_info_paths.iter().flat_map(|path| {
+            vec![path.path.hash40(), path.file_name.hash40(), path.parent.hash40(), path.ext.hash40()]
+        });
+        let dirs = tables.directories.iter().flat_map(|dir| vec![dir.hash40(), dir.name.hash40()]);
+
+        paths.chain(dirs).filter(|hash40| !self.names.contains_key(hash40)).collect()
+    }
+
+    /// Paths in `file_info_paths` without a label, grouped by parent and extension, largest
+    /// groups first. Stream entries have neither, so unlabeled streams share a group with both
+    /// set to zero
+    pub fn unlabeled_files(&self) -> Vec<UnlabeledGroup> {
+        let mut groups: HashMap<(u64, u64), HashSet<u64>> = HashMap::new();
+        for path in &self.tables.file_info_paths {
+            let hash40 = path.path.hash40();
+            if !self.names.contains_key(&hash40) {
+                groups.entry((path.parent.hash40(), path.ext.hash40())).or_default().insert(hash40);
+            }
+        }
+        for entry in &self.tables.stream_entries {
+            let hash40 = entry.hash as u64 + ((entry.name_length as u64) << 32);
+            if !self.names.contains_key(&hash40) {
+                groups.entry((0, 0)).or_default().insert(hash40);
+            }
+        }
+
+        let mut groups: Vec<_> = groups
+            .into_iter()
+            .map(|((parent, ext), hashes)| {
+                let mut hashes: Vec<_> = hashes.into_iter().collect();
+                hashes.sort();
+                UnlabeledGroup { parent, ext, hashes }
+            })
+            .collect();
+        groups.sort_by(|a, b| b.hashes.len().cmp(&a.hashes.len()).then((a.parent, a.ext).cmp(&(b.parent, b.ext))));
+
+        groups
+    }
+
+    /// Scan the decompressed data of every file for path-like strings and return the ones
+    /// matching unlabeled hashes, by hash40
+    pub fn discover_labels(&self) -> BTreeMap<u64, String> {
+        let unlabeled = self.unlabeled_hashes();
+        let extensions: Vec<ArcStr> = self.tables.file_info_paths
+            .iter()
+            .filter_map(|path| self.names.get(&path.ext.hash40()).cloned())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+
+        let mut slices: Vec<(usize, usize, u32)> = self.files
+            .values()
+            .filter_map(|file| match *file {
+                ArcFileInfo::Compressed { data, flags, .. } => Some((data.inner_ptr(), data.len(), flags)),
+                ArcFileInfo::Uncompressed { data, .. } => Some((data.inner_ptr(), data.len(), 0)),
+                _ => None,
+            })
+            .collect();
+        slices.sort();
+        slices.dedup();
+
+        slices
+            .par_iter()
+            .flat_map(|&(offset, len, flags)| {
+                let data = match decompress_data(&self.map[offset..offset + len], Compression::from_flags(flags)) {
+                    Ok(data) => data,
+                    Err(_) => return Vec::new(),
+                };
+                let mut found = Vec::new();
+                for string in path_strings(&data) {
+                    let lowercase = string.to_ascii_lowercase();
+                    for variant in [string, &lowercase[..]].iter() {
+                        for candidate in candidates(variant, &extensions) {
+                            let hash40 = hash40(&candidate);
+                            if unlabeled.contains(&hash40) {
+                                found.push((hash40, candidate.into_owned()));
+                            }
+                        }
+                    }
+                }
+                found
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn find_path_strings() {
+        let data = b"\x00\x01def_mario_001_col\x00\x10fighter/mario/model/body/c00/model.numdlb\x00ab\x00..1234..";
+        let strings: Vec<_> = path_strings(data).collect();
+        assert_eq!(strings, vec!["def_mario_001_col", "fighter/mario/model/body/c00/model.numdlb"]);
+    }
+
+    #[test]
+    fn label_candidates() {
+        let extensions = ["nutexb"];
+        let found: Vec<_> = candidates("fighter/mario/c00", &extensions).collect();
+        assert_eq!(found, vec![
+            "fighter/mario/c00", "fighter", "fighter/mario", "c00", "fighter/mario/c00.nutexb",
+        ]);
+
+        let found: Vec<_> = candidates("model.numdlb", &extensions).collect();
+        assert_eq!(found, vec!["model.numdlb"]);
+    }
+
+    #[test]
+    fn discover_from_contents() {
+        let arc = fixture::TestArc::new()
+            .file("fighter/mario/param.prc", b"\x00fighter/mario/model.numdlb\x00fighter/mario/body.nutexb\x00")
+            .file("fighter/mario/model.numdlb", b"mario model")
+            .file("fighter/mario/body.nutexb", b"mario texture")
+            .unlabeled("fighter/mario/body.nutexb")
+            .open("discover_from_contents");
+
+        let labels = arc.discover_labels();
+        // The texture's path and file name are found, the labeled model path and directories
+        // aren't reported again
+        let expected: BTreeMap<u64, String> = ["fighter/mario/body.nutexb", "body.nutexb"]
+            .iter()
+            .map(|label| (hash40(label), label.to_string()))
+            .collect();
+        assert_eq!(labels, expected);
+    }
+
+    #[test]
+    fn unlabeled_groups() {
+        let arc = fixture::TestArc::new()
+            .file("fighter/mario/model.numdlb", b"labeled")
+            .file("fighter/mario/body.nutexb", b"unlabeled")
+            .unlabeled("fighter/mario/body.nutexb")
+            .stream("stream:/sound/bgm/bgm_mario.nus3audio", b"labeled bgm")
+            .stream("stream:/sound/bgm/bgm_luigi.nus3audio", b"unlabeled bgm")
+            .unlabeled("stream:/sound/bgm/bgm_luigi.nus3audio")
+            .open("unlabeled_groups");
+
+        let groups = arc.unlabeled_files();
+        assert_eq!(groups.len(), 2);
+
+        let file = groups.iter().find(|group| group.parent != 0).unwrap();
+        assert_eq!(file.parent, hash40("fighter/mario"));
+        assert_eq!(file.ext, hash40("nutexb"));
+        assert_eq!(file.hashes, vec![hash40("fighter/mario/body.nutexb")]);
+
+        let stream = groups.iter().find(|group| group.parent == 0).unwrap();
+        assert_eq!(stream.ext, 0);
+        assert_eq!(stream.hashes, vec![hash40("stream:/sound/bgm/bgm_luigi.nus3audio")]);
+    }
+}
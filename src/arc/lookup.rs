@@ -0,0 +1,67 @@
+use super::*;
+
+/// Index stored with `hash40` in `groups`, which is sorted by hash40
+fn find_index(groups: &[HashIndexGroup], hash40: u64) -> Option<usize> {
+    let position = groups.binary_search_by_key(&hash40, HashIndexGroup::hash40).ok()?;
+    Some(groups[position].index())
+}
+
+/// Bucket of `buckets` holding `hash40`
+fn bucket(buckets: &[FileInfoBucket], hash40: u64) -> Option<FileInfoBucket> {
+    if buckets.is_empty() {
+        return None
+    }
+    Some(buckets[(hash40 % buckets.len() as u64) as usize])
+}
+
+fn path_index(buckets: &[FileInfoBucket], path_hash_to_index: &[HashIndexGroup], hash40: u64) -> Option<usize> {
+    let bucket = bucket(buckets, hash40)?;
+    find_index(path_hash_to_index.get(bucket.range())?, hash40)
+}
+
+impl Arc {
+    /// Index of the `FileInformationPath` of the file `hash40`, found through its bucket
+    pub fn file_path_index(&self, hash40: u64) -> Option<usize> {
+        path_index(&self.tables.file_info_buckets, &self.tables.path_hash_to_index, hash40)
+    }
+
+    /// Index of the `DirectoryInfo` of the directory `hash40`
+    pub fn directory_index(&self, hash40: u64) -> Option<usize> {
+        find_index(&self.tables.dir_hash_to_index, hash40)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn group(hash40: u64, index: usize) -> HashIndexGroup {
+        HashIndexGroup::new(hash40, index)
+    }
+
+    /// The fixture lays the buckets out the way the lookup reads them, so this only checks the
+    /// lookup against itself. `verify` checks the layout on real arcs
+    #[test]
+    fn parsed_tables() {
+        let arc = fixture::sample().open("lookup");
+        let tables = &arc.tables;
+        assert!(!tables.file_info_buckets.is_empty());
+        for (index, path) in tables.file_info_paths.iter().enumerate() {
+            assert_eq!(arc.file_path_index(path.path.hash40()), Some(index));
+        }
+        for (index, dir) in tables.directories.iter().enumerate() {
+            assert_eq!(arc.directory_index(dir.hash40()), Some(index));
+        }
+        assert_eq!(arc.file_path_index(hash40("fighter/peach/model.numdlb")), None);
+        assert_eq!(arc.directory_index(hash40("fighter/peach")), None);
+    }
+
+    #[test]
+    fn sorted_index() {
+        let mut groups = vec![group(hash40("fighter"), 1), group(hash40("fighter/mario"), 0), group(hash40("ui"), 2)];
+        groups.sort_by_key(HashIndexGroup::hash40);
+        assert_eq!(find_index(&groups, hash40("fighter/mario")), Some(0));
+        assert_eq!(find_index(&groups, hash40("ui")), Some(2));
+        assert_eq!(find_index(&groups, hash40("stage")), None);
+    }
+}
@@ -0,0 +1,143 @@
+use super::*;
+use serde::Serialize;
+use util::{serialize_hash40, serialize_hash40_opt};
+
+/// Everything the tables say about a single `FileInfo2` row or stream file, flattened for
+/// spreadsheets
+#[derive(Debug, Clone, Serialize)]
+pub struct ManifestEntry {
+    /// Full path, or the hash40 when it isn't labeled
+    pub path: String,
+    #[serde(serialize_with = "serialize_hash40")]
+    pub hash40: u64,
+    /// Index of the `FileInfo2` row, files with regional variants have a row per region
+    pub file_info_index: Option<usize>,
+    /// Which of the rows sharing this path this is, in table order
+    pub region: Option<usize>,
+    #[serde(serialize_with = "serialize_hash40_opt")]
+    pub parent: Option<u64>,
+    #[serde(serialize_with = "serialize_hash40_opt")]
+    pub ext: Option<u64>,
+    /// Absolute offset of the data in the arc
+    pub offset: u64,
+    pub comp_size: u64,
+    pub decomp_size: u64,
+    pub file_info_flags: Option<u32>,
+    pub sub_file_flags: Option<u32>,
+    /// Path hash of the file whose data this file uses
+    #[serde(serialize_with = "serialize_hash40_opt")]
+    pub redirect: Option<u64>,
+    /// `StreamEntry.flags` for stream files, whose data is stored uncompressed outside the
+    /// file section
+    pub stream_flags: Option<u32>,
+}
+
+impl Arc {
+    /// Path of `hash40`, or the hash40 when it isn't labeled
+    pub fn display_path(&self, hash40: u64) -> String {
+        match self.get_name(hash40) {
+            Some(name) => name.to_owned(),
+            None => format!("0x{:010x}", hash40),
+        }
+    }
+
+    /// One entry per `FileInfo2` row and stream file, in table order
+    pub fn manifest(&self) -> Vec<ManifestEntry> {
+        let tables = &self.tables;
+        let mut regions: HashMap<u64, usize> = HashMap::new();
+        let mut entries = Vec::with_capacity(tables.file_infos_v2.len() + tables.stream_entries.len());
+
+        for (index, file_info) in tables.file_infos_v2.iter().enumerate() {
+            let path = tables.file_info_paths[file_info.hash_index as usize];
+            let hash40 = path.path.hash40();
+            let region = regions.entry(hash40).or_insert(0);
+            *region += 1;
+            let sub_index = Arc::get_sub_index(tables, file_info);
+            let sub_file = tables.sub_files[sub_index.sub_file_index as usize];
+            let (data, decomp_size, _) = Arc::get_file_compressed(tables, file_info);
+            let redirect = if file_info.flags & REDIRECT != 0 {
+                let file_index = tables.file_info_indices[file_info.hash_index_2 as usize];
+                let target = tables.file_infos_v2[file_index.file_info_index as usize];
+                Some(tables.file_info_paths[target.hash_index as usize].path.hash40())
+            } else {
+                None
+            };
+
+            entries.push(ManifestEntry {
+                path: self.display_path(hash40),
+                hash40,
+                file_info_index: Some(index),
+                region: Some(*region - 1),
+                parent: Some(path.parent.hash40()),
+                ext: Some(path.ext.hash40()),
+                offset: data.inner_ptr() as u64,
+                comp_size: data.len() as u64,
+                decomp_size,
+                file_info_flags: Some(file_info.flags),
+                sub_file_flags: Some(sub_file.flags),
+                redirect,
+                stream_flags: None,
+            });
+        }
+
+        for entry in &tables.stream_entries {
+            let hash40 = entry.hash as u64 + ((entry.name_length as u64) << 32);
+            let offset_entry = tables.stream_offset_entries[
+                tables.stream_file_indices[entry.index as usize] as usize
+            ];
+            // Streams don't store their parent or extension, they can only be worked out from labels
+            let name = self.get_name(hash40);
+            let parent = name.and_then(|name| Some(super::hash40(&name[..name.rfind('/')?])));
+            let ext = name.and_then(|name| {
+                let file_name = name.rsplit('/').next()?;
+                Some(super::hash40(&file_name[file_name.rfind('.')? + 1..]))
+            });
+
+            entries.push(ManifestEntry {
+                path: self.display_path(hash40),
+                hash40,
+                file_info_index: None,
+                region: None,
+                parent,
+                ext,
+                offset: offset_entry.offset,
+                comp_size: offset_entry.size,
+                decomp_size: offset_entry.size,
+                file_info_flags: None,
+                sub_file_flags: None,
+                redirect: None,
+                stream_flags: Some(entry.flags),
+            });
+        }
+
+        entries
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn regional_rows() {
+        let arc = fixture::sample()
+            .regional_file("ui/param/ui_stage_db.prc", &[b"stage db", b"stage db us", b"stage db eu"])
+            .open("manifest");
+        let manifest = arc.manifest();
+        assert_eq!(manifest.len(), arc.tables.file_infos_v2.len() + 1);
+
+        let rows: Vec<_> = manifest.iter().filter(|entry| entry.path == "ui/param/ui_stage_db.prc").collect();
+        assert_eq!(rows.iter().map(|entry| entry.region).collect::<Vec<_>>(), vec![Some(0), Some(1), Some(2)]);
+        assert_eq!(rows.iter().map(|entry| entry.decomp_size).collect::<Vec<_>>(), vec![8, 11, 11]);
+        for entry in &rows {
+            let file_info = arc.tables.file_infos_v2[entry.file_info_index.unwrap()];
+            assert_eq!(arc.tables.file_info_paths[file_info.hash_index as usize].path.hash40(), entry.hash40);
+        }
+        // The regional rows point at their own data
+        assert_ne!(rows[1].offset, rows[2].offset);
+
+        let stream = manifest.last().unwrap();
+        assert_eq!(stream.path, "stream:/sound/bgm/bgm_mario.nus3audio");
+        assert_eq!((stream.file_info_index, stream.region, stream.decomp_size), (None, None, 9));
+    }
+}
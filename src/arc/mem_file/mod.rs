@@ -3,7 +3,7 @@ use std::ops::Deref;
 use std::marker::PhantomData;
 use std::mem::{size_of, transmute};
 use std::borrow::Borrow;
-use std::sync::RwLock;
+use std::sync::{Mutex, PoisonError, RwLock};
 use std::ops::Add;
 
 #[cfg(test)]
@@ -13,11 +13,33 @@ pub trait Num:  Copy + IntoUsize + Add<Output=Self> + Sized {}
 impl<T> Num for T where T: Copy + IntoUsize + Add<Output=Self> + Sized {}
 
 lazy_static::lazy_static! {
-    pub static ref FILE: RwLock<Option<&'static [u8]>> = RwLock::new(None);
+    static ref FILE: RwLock<Option<&'static [u8]>> = RwLock::new(None);
+    /// Held for as long as a file is set, so only one file is read through at a time
+    static ref FILE_LOCK: Mutex<()> = Mutex::new(());
 }
 
-pub fn set_file(file: &[u8]) {
-    *FILE.write().unwrap() = Some(unsafe { transmute(file) });
+/// Unsets the file when `with_file` returns or panics
+struct ResetFile;
+
+impl Drop for ResetFile {
+    fn drop(&mut self) {
+        *FILE.write().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+}
+
+/// Run `f` with `file` as the file every `FilePtr` and `FileSlice` reads from. The file is only
+/// set for the duration of `f`, and other threads wait for it to finish before setting theirs
+pub fn with_file<R>(file: &[u8], f: impl FnOnce() -> R) -> R {
+    let _lock = FILE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    *FILE.write().unwrap_or_else(PoisonError::into_inner) = Some(unsafe { transmute(file) });
+    let _reset = ResetFile;
+    f()
+}
+
+fn file() -> &'static [u8] {
+    FILE.read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .expect("FilePtr and FileSlice can only be read inside with_file")
 }
 
 pub fn get_header<T: Sized>() -> FilePtr<usize, T> {
@@ -25,12 +47,12 @@ pub fn get_header<T: Sized>() -> FilePtr<usize, T> {
 }
 
 pub fn get_footer<T: Sized>() -> FilePtr<usize, T> {
-    let file = (*FILE.read().unwrap()).unwrap();
+    let file = file();
     FilePtr::new(file.len() - size_of::<T>())
 }
 
 pub fn get_file_size() -> usize {
-    (*FILE.read().unwrap()).unwrap().len()
+    file().len()
 }
 
 #[repr(transparent)]
@@ -121,7 +143,7 @@ impl<P: Num, T: Sized> Deref for FilePtr<P, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        let file = (*FILE.read().unwrap()).unwrap();
+        let file = file();
 
         if self.0.into() + size_of::<T>() > file.len() {
             panic!(
@@ -142,7 +164,7 @@ impl<T: Sized> Deref for FileSlice<T> {
     type Target = [T];
 
     fn deref(&self) -> &Self::Target {
-        let file = (*FILE.read().unwrap()).unwrap();
+        let file = file();
 
         if self.0 + (size_of::<T>() * self.1) > file.len() {
             panic!(
@@ -1,32 +1,96 @@
 #![allow(dead_code)]
-use std::collections::{HashMap, HashSet};
-use std::sync::Mutex;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, hash_map::Entry};
 use std::path::Path;
 use std::fs::File;
 use std::io;
+use std::sync::{Mutex, PoisonError};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
 
 use rayon::prelude::*;
 
 mod util;
 mod structs;
 mod mem_file;
-use mem_file::{set_file, get_header, FilePtr64, FileSlice};
+mod cache;
+mod writer;
+pub mod diff;
+pub mod manifest;
+pub mod verify;
+pub mod labels;
+pub mod find;
+pub mod inspect;
+pub mod tables;
+mod lookup;
+mod info;
+#[cfg(test)]
+pub(crate) mod fixture;
+pub use diff::{ArcDiff, Change, FileChange};
+pub use writer::Changes;
+use mem_file::{with_file, get_header, FilePtr64, FileSlice};
 use crc::crc32::checksum_ieee as crc32;
 use structs::*;
 use memmap::Mmap;
 use packed_struct::prelude::*;
-use cached::{cached_key, SizedCache};
+use cached::{cached_key_result, SizedCache};
 
 static HASH_STRINGS: ArcStr = include_str!("hash40s.tsv");
 
 //include!(concat!(env!("OUT_DIR"), "/hash40s.rs"));
 
-cached_key!{
-    FILE_CACHE: SizedCache<u64, Option<Vec<u8>>> = SizedCache::with_size(50);
-    Key = { hash40 };
-    fn decompress_file(hash40: u64, file: FileSlice<u8>) -> Option<Vec<u8>> = {
-        let reader = io::Cursor::new(&*file);
-        zstd::decode_all(reader).ok()
+cached_key_result!{
+    FILE_CACHE: SizedCache<(usize, u64), Vec<u8>> = SizedCache::with_size(50);
+    Key = { (data.as_ptr() as usize, hash40) };
+    fn decompress_file(hash40: u64, data: &[u8], compression: Compression) -> io::Result<Vec<u8>> = {
+        decompress_data(data, compression).map(Cow::into_owned)
+    }
+}
+
+/// Number of files `get_file_data` failed to decompress since the program started
+static DECOMPRESSION_FAILURES: AtomicU64 = AtomicU64::new(0);
+
+pub fn decompression_failures() -> u64 {
+    DECOMPRESSION_FAILURES.load(Ordering::Relaxed)
+}
+
+lazy_static::lazy_static! {
+    /// Files whose decompression failure was already logged as an error. Failures aren't
+    /// cached, so without this every read of a corrupt file would log it again
+    static ref LOGGED_FAILURES: Mutex<HashSet<u64>> = Mutex::new(HashSet::new());
+}
+
+/// How the data of a sub file is stored, from its `SubFileInfo` flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Zstd,
+    /// Compressed with something other than zstd, holding the flags
+    Unknown(u32),
+}
+
+impl Compression {
+    pub fn from_flags(flags: u32) -> Self {
+        if flags & SUB_FILE_COMPRESSED == 0 {
+            Compression::None
+        } else if flags & SUB_FILE_ZSTD != 0 {
+            Compression::Zstd
+        } else {
+            Compression::Unknown(flags)
+        }
+    }
+}
+
+/// Decompress the stored data of a file
+fn decompress_data(data: &[u8], compression: Compression) -> io::Result<Cow<'_, [u8]>> {
+    match compression {
+        Compression::None => Ok(Cow::Borrowed(data)),
+        Compression::Zstd => zstd::decode_all(data).map(Cow::Owned),
+        Compression::Unknown(flags) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown compression (sub file flags 0x{:x})", flags),
+        )),
     }
 }
 
@@ -44,6 +108,8 @@ pub enum ArcFileInfo {
     Compressed {
         data: FileSlice<u8>,
         decomp_size: u64,
+        /// `SubFileInfo` flags
+        flags: u32,
     },
     Directory,
     None
@@ -51,20 +117,59 @@ pub enum ArcFileInfo {
 
 type ArcStr = &'static str;
 
-pub struct ArcInternal<'a> {
-    pub arc_header: &'a ArcHeader,
+/// Offset of every table in the decompressed file system table
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TableOffsets {
+    pub quick_dirs: usize,
+    pub stream_hashes: usize,
+    pub stream_entries: usize,
+    pub stream_file_indices: usize,
+    pub stream_offset_entries: usize,
+    pub path_lookup_header: usize,
+    pub file_info_buckets: usize,
+    pub path_hash_to_index: usize,
+    pub file_info_paths: usize,
+    pub file_info_indices: usize,
+    pub dir_hash_to_index: usize,
+    pub directories: usize,
+    pub folder_offsets: usize,
+    pub folder_child_hashes: usize,
+    pub file_infos_v2: usize,
+    pub file_info_sub_index: usize,
+    pub sub_files: usize,
+    pub end: usize,
+}
+
+#[derive(Clone)]
+pub struct ArcInternal {
+    pub arc_header: ArcHeader,
+    pub fs_header: FileSystemHeader,
+    /// The decompressed file system table the other tables were read from
+    pub table: Vec<u8>,
+    pub offsets: TableOffsets,
     pub stream_entries: Vec<StreamEntry>,
-    pub stream_file_indices: &'a [u32],
-    pub stream_offset_entries: &'a [StreamOffsetEntry],
-    pub file_info_paths: &'a [FileInformationPath],
-    pub file_info_indices: &'a [FileInformationIndex],
-    pub dir_hash_to_index: &'a [HashIndexGroup],
-    pub directories: &'a [DirectoryInfo],
-    pub file_infos_v2: &'a [FileInfo2],
-    pub file_info_sub_index: &'a [FileInfoSubIndex],
-    pub sub_files: &'a [SubFileInfo],
-    pub quick_dirs: &'a [QuickDir],
-    pub folder_offsets: &'a [DirectoryOffsets],
+    pub stream_file_indices: Vec<u32>,
+    pub stream_offset_entries: Vec<StreamOffsetEntry>,
+    pub file_info_buckets: Vec<FileInfoBucket>,
+    /// `FileInformationPath` index of every path hash40, grouped by `file_info_buckets`
+    pub path_hash_to_index: Vec<HashIndexGroup>,
+    pub file_info_paths: Vec<FileInformationPath>,
+    pub file_info_indices: Vec<FileInformationIndex>,
+    /// `DirectoryInfo` index of every directory hash40, sorted by hash40
+    pub dir_hash_to_index: Vec<HashIndexGroup>,
+    pub directories: Vec<DirectoryInfo>,
+    pub file_infos_v2: Vec<FileInfo2>,
+    pub file_info_sub_index: Vec<FileInfoSubIndex>,
+    pub sub_files: Vec<SubFileInfo>,
+    pub quick_dirs: Vec<QuickDir>,
+    pub folder_offsets: Vec<DirectoryOffsets>,
+    pub folder_child_hashes: Vec<HashIndexGroup>,
+}
+
+/// Tree state for arcs opened with `Arc::open_lazy`
+struct LazyTree {
+    names: Option<JoinHandle<HashMap<u64, ArcStr>>>,
+    loaded: HashSet<u64>,
 }
 
 pub struct Arc {
@@ -73,36 +178,155 @@ pub struct Arc {
     pub names: HashMap<u64, ArcStr>,
     pub stream_paths: HashMap<u64, ArcStr>,
     pub dir_children: HashMap<u64, HashSet<u64>>,
+    /// Children of every directory by name, for `get_child`
+    child_names: HashMap<u64, HashMap<ArcStr, u64>>,
     pub files: HashMap<u64, ArcFileInfo>,
     pub stems: HashMap<u64, ArcStr>,
+    pub tables: ArcInternal,
+    lazy: Option<LazyTree>,
 }
 
-impl<'a> Arc {
+impl Arc {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+        let mut arc = Arc::parse(path)?;
+
+        arc.names.extend(Arc::load_hashes());
+        arc.load_stream_files();
+        let start = Instant::now();
+        arc.load_directories();
+        debug!(target: "parser", "Loaded {} files and directories in {:?}", arc.files.len(), start.elapsed());
+
+        Ok(arc)
+    }
+
+    /// Open an arc using the index cached in `cache_dir`, or build the tree and cache it if
+    /// there is no index for this version of the arc yet
+    pub fn open_cached<P: AsRef<Path>, C: AsRef<Path>>(path: P, cache_dir: C) -> Result<Self, io::Error> {
+        let mut arc = Arc::parse(path.as_ref())?;
+        let key = cache::CacheKey::new(path, &arc.tables.arc_header)?;
+        let index_path = cache_dir.as_ref().join(key.file_name());
+
+        match cache::read_index(&index_path, &key) {
+            Ok(index) => {
+                arc.names = index.names;
+                arc.stream_paths = index.stream_paths;
+                arc.dir_children = index.dir_children;
+                arc.files = index.files;
+                arc.stems = index.stems;
+                arc.index_child_names();
+            }
+            Err(err) => {
+                info!(target: "cache", "No usable index at {}: {}", index_path.display(), err);
+                arc.names.extend(Arc::load_hashes());
+                arc.load_stream_files();
+                arc.load_directories();
+
+                let written = std::fs::create_dir_all(cache_dir.as_ref())
+                    .and_then(|_| cache::write_index(&index_path, &key, &arc));
+                if let Err(err) = written {
+                    warn!(target: "cache", "Failed to write index to {}: {}", index_path.display(), err);
+                }
+            }
+        }
+
+        Ok(arc)
+    }
+
+    /// Open an arc with only the root directory loaded. Labels are loaded in the background
+    /// and the rest of the tree is built as it is accessed, see `load_dir`
+    pub fn open_lazy<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+        let mut arc = Arc::parse(path)?;
+
+        arc.lazy = Some(LazyTree {
+            names: Some(thread::spawn(Arc::load_hashes)),
+            loaded: HashSet::new(),
+        });
+
+        Ok(arc)
+    }
+
+    /// Make sure the children of the directory `hash40` are loaded. Does nothing for arcs that
+    /// weren't opened with `open_lazy`
+    pub fn load_dir(&mut self, hash40: u64) {
+        let names = match &mut self.lazy {
+            Some(lazy) => lazy.names.take(),
+            None => return
+        };
+
+        // First access, finish loading the labels and add every directory to the tree
+        if let Some(names) = names {
+            self.names.extend(names.join().unwrap());
+            self.load_stream_files();
+            self.load_directory_nodes();
+        }
+
+        if self.lazy.as_mut().unwrap().loaded.insert(hash40) {
+            let index = self.directory_index(hash40).filter(|&index| index < self.tables.directories.len());
+            if let Some(index) = index {
+                self.load_directory_contents(index);
+            }
+        }
+    }
+
+    /// Make sure the file `hash40` is loaded by loading the directory it's in. Does nothing for
+    /// arcs that weren't opened with `open_lazy`
+    pub fn load_file(&mut self, hash40: u64) {
+        self.load_dir(0);
+        if self.lazy.is_none() || self.files.contains_key(&hash40) {
+            return
+        }
+
+        let parent = self.file_path_index(hash40)
+            .and_then(|index| self.tables.file_info_paths.get(index))
+            .map(|path| path.parent.hash40());
+        if let Some(parent) = parent {
+            self.load_dir(parent);
+        }
+    }
+
+    fn parse<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
         let file = File::open(path.as_ref())?;
         let map = unsafe { Mmap::map(&file) }?;
 
+        let tables = Arc::parse_tables(&map)?;
+
         let mut arc = Arc {
             file,
             map,
             stream_paths: HashMap::new(),
             names: HashMap::new(),
             dir_children: HashMap::new(),
+            child_names: HashMap::new(),
             files: HashMap::new(),
             stems: HashMap::new(),
+            tables,
+            lazy: None,
         };
 
-        set_file(&*arc.map);
+        arc.dir_children.insert(0, HashSet::new());
+        arc.names.insert(0, "");
+        arc.stems.insert(0, "");
+        arc.files.insert(0, ArcFileInfo::Directory);
 
-        let arc_header = &*get_header::<ArcHeader>();
-        let decomp_table = arc.decompress_table().unwrap();
+        Ok(arc)
+    }
 
-        set_file(&decomp_table);
+    fn parse_tables(file: &[u8]) -> Result<ArcInternal, io::Error> {
+        let (arc_header, decomp_table) = with_file(file, || -> io::Result<_> {
+            Ok((*get_header::<ArcHeader>(), Arc::decompress_table()?))
+        })?;
+        let mut tables = with_file(&decomp_table, || Arc::parse_file_system(arc_header))?;
+        tables.table = decomp_table;
+
+        Ok(tables)
+    }
 
+    /// Read every table of the decompressed file system table, which must be the current file
+    fn parse_file_system(arc_header: ArcHeader) -> Result<ArcInternal, io::Error> {
         let fs_header = get_header::<FileSystemHeader>();
         let stream_header = FilePtr64::<StreamHeader>::new(0x100);
 
-        dbg!(stream_header.quick_dir_count);
+        debug!(target: "parser", "quick_dir_count: {}", { stream_header.quick_dir_count });
 
         // ArcCross names:
         // streamUnk
@@ -110,11 +334,9 @@ impl<'a> Arc {
         let quick_dirs = quick_dirs_ptr.iter()
                             .map(|a| QuickDir::unpack(a))
                             .collect::<Result<Vec<_>, _>>().unwrap();
-        //println!("streamUnk: {:X}", quick_dirs_ptr.inner_ptr());
         // streamHashToName
         let count = stream_header.stream_hash_count as usize;
         let stream_hashes = quick_dirs_ptr.next_slice::<u64>(count);
-        //println!("streamHashToName: {:X}", stream_hashes.inner_ptr());
 
         // streamNameToHash
         let stream_entries_ptr = stream_hashes.next_slice::<[u8; 0xC]>(count);
@@ -122,125 +344,122 @@ impl<'a> Arc {
                                 .iter()
                                 .map(|a| StreamEntry::unpack(a))
                                 .collect::<Result<Vec<_>, _>>().unwrap();
-        //println!("streamNameToHash: {:X}", stream_entries_ptr.inner_ptr());
 
         // streamIndexToFile
         let count = stream_header.stream_file_index_count as usize;
         let stream_file_indices = stream_entries_ptr.next_slice::<u32>(count);
-        //println!("streamIndexToFile: {:X}", stream_file_indices.inner_ptr());
 
         // streamOffsets
         let count = stream_header.stream_offset_entry_count as usize;
         let stream_offset_entries = stream_file_indices.next_slice::<StreamOffsetEntry>(count);
-        //println!("streamOffsets: {:X}", stream_offset_entries.inner_ptr());
 
         // ----- Compressed stuff ------
         // unkCount1, unkCount2
-        let unk_counts = stream_offset_entries.next::<[u32; 2]>();
-        dbg!(&*unk_counts);
-        //println!("unkCount1: {:X}", unk_counts.inner());
+        let path_lookup_header = stream_offset_entries.next::<FilePathLookupHeader>();
+        debug!(target: "parser", "{:?}", *path_lookup_header);
 
         // fileInfoUnknownTable
-        let file_info_unks = unk_counts.next_slice::<FileInformationUnknownTable>(unk_counts[1] as usize);
-        //std::fs::write("fileInfoUnknownTable.bin", &*file_info_unks.as_byte_file_slice()).unwrap();
-        //println!("fileInfoUnknownTable: {:X}", file_info_unks.inner_ptr());
+        let count = path_lookup_header.bucket_count as usize;
+        let file_info_buckets = path_lookup_header.next_slice::<FileInfoBucket>(count);
 
         // filePathToIndexHashGroup
-        let hash_index_groups = file_info_unks.next_slice::<HashIndexGroup>(unk_counts[0] as usize);
-        //std::fs::write("filePathToIndexHashGroup.bin", &*hash_index_groups.as_byte_file_slice()).unwrap();
-        //println!("filePathToIndexHashGroup: {:X}", hash_index_groups.inner_ptr());
+        let count = path_lookup_header.path_hash_count as usize;
+        let path_hash_to_index = file_info_buckets.next_slice::<HashIndexGroup>(count);
 
         // fileInfoPath
-        let file_info_paths = hash_index_groups
+        let file_info_paths = path_hash_to_index
                             .next_slice::<FileInformationPath>(fs_header.file_info_path_count as _);
-        //println!("fileInfoPath: {:X}", file_infos.inner_ptr());
 
         // fileInfoIndex
         let count = fs_header.file_info_index_count as usize;
         let file_info_indices = file_info_paths.next_slice::<FileInformationIndex>(count);
-        //println!("fileInfoIndex: {:X}", file_info_indices.inner_ptr());
 
         // directoryHashGroup
         let folder_count = fs_header.folder_count as usize;
         let dir_hash_to_index = file_info_indices.next_slice::<HashIndexGroup>(folder_count);
-        //println!("directoryHashGroup: {:X}", dir_hash_to_index.inner_ptr());
 
         // directoryList
         let dirs = dir_hash_to_index.next_slice::<DirectoryInfo>(folder_count);
-        //println!("directoryList: {:X}", dirs.inner_ptr());
-
-        use std::mem::size_of;
-        dbg!(size_of::<DirectoryOffsets>());
 
         // directoryOffsets
+        debug!(
+            target: "parser", "folder_offset_count_1: {}, folder_offset_count_2: {}, extra_folder: {}",
+            { fs_header.folder_offset_count_1 }, { fs_header.folder_offset_count_2 }, { fs_header.extra_folder }
+        );
         let folder_offsets = dirs.next_slice::<DirectoryOffsets>(
-            dbg!(fs_header.folder_offset_count_1) as usize +
-            dbg!(fs_header.folder_offset_count_2) as usize +
-            dbg!(fs_header.extra_folder) as usize
+            fs_header.folder_offset_count_1 as usize +
+            fs_header.folder_offset_count_2 as usize +
+            fs_header.extra_folder as usize
         );
-        //println!("directoryOffsets: {:X}", folder_offsets.inner_ptr());
 
         // directoryChildHashGroup
         let count = fs_header.hash_folder_count as usize;
         let folder_child_hashes = folder_offsets.next_slice::<HashIndexGroup>(count);
-        //println!("directoryChildHashGroup: {:X}", folder_child_hashes.inner_ptr());
 
         // fileInfoV2
         let count = fs_header.file_info_count as usize +
                     fs_header.sub_file_count_2 as usize +
                     fs_header.extra_count as usize;
         let file_infos_v2 = folder_child_hashes.next_slice::<FileInfo2>(count);
-        //println!("fileInfoV2: {:X}", file_infos_v2.inner_ptr());
 
         // fileInfoSubIndex
         let count = fs_header.file_info_sub_index_count as usize +
                     fs_header.sub_file_count_2 as usize +
                     fs_header.extra_count_2 as usize;
         let file_info_sub_index = file_infos_v2.next_slice::<FileInfoSubIndex>(count);
-        //println!("fileInfoSubIndex: {:X}", file_info_sub_index.inner_ptr());
 
         // subFiles
         let count = fs_header.sub_file_count as usize +
                     fs_header.sub_file_count_2 as usize +
                     fs_header.extra_count as usize;
         let sub_files = file_info_sub_index.next_slice::<SubFileInfo>(count);
-        //println!("subFiles: {:X}", sub_files.inner_ptr());
         let end = sub_files.next::<()>().inner();
-        dbg!(end);
-        dbg!(*fs_header);
+        debug!(target: "parser", "File system table end: 0x{:x}", end);
+        debug!(target: "parser", "{:?}", *fs_header);
+
+        let offsets = TableOffsets {
+            quick_dirs: quick_dirs_ptr.inner_ptr(),
+            stream_hashes: stream_hashes.inner_ptr(),
+            stream_entries: stream_entries_ptr.inner_ptr(),
+            stream_file_indices: stream_file_indices.inner_ptr(),
+            stream_offset_entries: stream_offset_entries.inner_ptr(),
+            path_lookup_header: path_lookup_header.inner(),
+            file_info_buckets: file_info_buckets.inner_ptr(),
+            path_hash_to_index: path_hash_to_index.inner_ptr(),
+            file_info_paths: file_info_paths.inner_ptr(),
+            file_info_indices: file_info_indices.inner_ptr(),
+            dir_hash_to_index: dir_hash_to_index.inner_ptr(),
+            directories: dirs.inner_ptr(),
+            folder_offsets: folder_offsets.inner_ptr(),
+            folder_child_hashes: folder_child_hashes.inner_ptr(),
+            file_infos_v2: file_infos_v2.inner_ptr(),
+            file_info_sub_index: file_info_sub_index.inner_ptr(),
+            sub_files: sub_files.inner_ptr(),
+            end,
+        };
 
         // stream_entries, stream_offest_entries, stream_file_indices
-        let arc_internal = ArcInternal {
+        Ok(ArcInternal {
             arc_header,
-            dir_hash_to_index: &dir_hash_to_index,
-            directories: &dirs,
-            file_info_indices: &file_info_indices,
-            file_info_sub_index: &file_info_sub_index,
-            file_info_paths: &file_info_paths,
-            file_infos_v2: &file_infos_v2,
-            sub_files: &sub_files,
-            stream_entries: stream_entries,
-            stream_file_indices: &stream_file_indices,
-            stream_offset_entries: &stream_offset_entries,
-            quick_dirs: &quick_dirs,
-            folder_offsets: &folder_offsets,
-        };
-        
-
-        arc.load_hashes();
-        arc.load_stream_files(&arc_internal);
-        use timeit::*;
-        let load_time = timeit_loops!(1, {
-            arc.load_compressed_files(&arc_internal);
-        });
-        dbg!(load_time);
-        set_file(&*arc.map);
-
-        // Arc tree
-        // println!("Tree\n----");
-        // arc.print_tree(0, 0);
-
-        Ok(arc)
+            fs_header: *fs_header,
+            offsets,
+            file_info_buckets: file_info_buckets.to_vec(),
+            path_hash_to_index: path_hash_to_index.to_vec(),
+            dir_hash_to_index: dir_hash_to_index.to_vec(),
+            directories: dirs.to_vec(),
+            file_info_indices: file_info_indices.to_vec(),
+            file_info_sub_index: file_info_sub_index.to_vec(),
+            file_info_paths: file_info_paths.to_vec(),
+            file_infos_v2: file_infos_v2.to_vec(),
+            sub_files: sub_files.to_vec(),
+            stream_entries,
+            stream_file_indices: stream_file_indices.to_vec(),
+            stream_offset_entries: stream_offset_entries.to_vec(),
+            quick_dirs,
+            folder_offsets: folder_offsets.to_vec(),
+            folder_child_hashes: folder_child_hashes.to_vec(),
+            table: Vec::new(),
+        })
     }
 
     pub fn get_name(&self, hash40: u64) -> Option<&'static str> {
@@ -254,57 +473,65 @@ impl<'a> Arc {
         x.map(|a| *a)
     }
 
-    pub fn get_file_data(&self, hash40: u64) -> Option<FileSliceOrVec> {
+    /// Data of the file `hash40`, failing with `NotFound` if there is no such file and with
+    /// `InvalidData` if it can't be decompressed
+    pub fn get_file_data(&self, hash40: u64) -> io::Result<FileSliceOrVec<'_>> {
         match self.files.get(&hash40) {
             Some(&ArcFileInfo::Uncompressed {
                 data, ..
             }) => {
-                Some(FileSliceOrVec::FileSlice(data))
+                Ok(FileSliceOrVec::Slice(self.slice(data)))
             }
             Some(&ArcFileInfo::Compressed {
-                data, decomp_size
+                data, flags, ..
             }) => {
-                if data.len() == decomp_size as usize {
-                    Some(FileSliceOrVec::FileSlice(data))
-                } else {
-                    let f = decompress_file(hash40, data)?;
-                    Some(FileSliceOrVec::Vec(f))
+                match Compression::from_flags(flags) {
+                    Compression::None => Ok(FileSliceOrVec::Slice(self.slice(data))),
+                    compression => {
+                        let f = decompress_file(hash40, self.slice(data), compression).map_err(|err| {
+                            DECOMPRESSION_FAILURES.fetch_add(1, Ordering::Relaxed);
+                            let first = LOGGED_FAILURES.lock().unwrap_or_else(PoisonError::into_inner).insert(hash40);
+                            let level = if first { log::Level::Error } else { log::Level::Debug };
+                            log!(
+                                target: "parser", level, "Failed to decompress 0x{:010x} ({}) at 0x{:x}: {}",
+                                hash40, self.display_path(hash40), data.inner_ptr(), err
+                            );
+                            io::Error::new(io::ErrorKind::InvalidData, err)
+                        })?;
+                        Ok(FileSliceOrVec::Vec(f))
+                    }
                 }
             }
-            _ => {
-                eprintln!("File not found");
-                None
-            }
+            _ => Err(io::Error::new(io::ErrorKind::NotFound, format!("0x{:010x} isn't a file", hash40))),
         }
     }
 
-    fn print_tree(&self, node: u64, depth: u64) {
-        for _ in 0..depth {
-            print!("    ");
-        }
-        println!("{}", self.stems.get(&node).unwrap_or(&"error"));
-        if let Some(ArcFileInfo::Directory) = self.files.get(&node) {
-            for child in self.dir_children.get(&node).unwrap() {
-                self.print_tree(*child, depth + 1);
+    /// Decompressed data of the file `hash40`, bypassing the decompression cache
+    pub fn read_file(&self, hash40: u64) -> io::Result<Vec<u8>> {
+        match self.files.get(&hash40) {
+            Some(&ArcFileInfo::Uncompressed { data, .. }) => Ok(self.slice(data).to_vec()),
+            Some(&ArcFileInfo::Compressed { data, flags, .. }) => {
+                decompress_data(self.slice(data), Compression::from_flags(flags)).map(Cow::into_owned)
             }
+            _ => Err(io::Error::new(io::ErrorKind::NotFound, format!("0x{:010x} isn't a file", hash40))),
         }
     }
 
-    fn compressed_table(&self) -> FileSlice<u8> {
+    fn compressed_table() -> FileSlice<u8> {
         let arc = get_header::<ArcHeader>();
         let comp_table_hdr = &arc.file_system;
         comp_table_hdr.next_slice(comp_table_hdr.comp_size as _)
     }
 
-    fn decompress_table(&mut self) -> io::Result<Vec<u8>> {
-        let compressed_table = self.compressed_table();
+    fn decompress_table() -> io::Result<Vec<u8>> {
+        let compressed_table = Arc::compressed_table();
         let compressed_table = io::Cursor::new(&*compressed_table);
         zstd::stream::decode_all(compressed_table)
     }
     
-    fn load_hashes(&mut self) {
-        self.names =
-            HASH_STRINGS
+    /// Labels from `hash40s.tsv`, by hash40
+    pub fn load_hashes() -> HashMap<u64, ArcStr> {
+        HASH_STRINGS
                 .par_split('\n')
                 .filter_map(|line|{
                     let split: Vec<ArcStr> = line.split('\t').collect();
@@ -314,28 +541,21 @@ impl<'a> Arc {
                         None
                     }
                 })
-                .collect();
+                .collect()
     }
 
     fn add_dir(&mut self, parent: ArcStr, dir: ArcStr) -> u64 {
-        let dirs = &mut self.dir_children;
         let (parent_hash40, dir_hash40) = (hash40(parent), hash40(dir));
-        if !dirs.contains_key(&parent_hash40) {
-            dirs.insert(parent_hash40, HashSet::new());
-        }
-        let parent_children = dirs.get_mut(&parent_hash40).unwrap();
-        if dir_hash40 != 0 {
-            parent_children.insert(dir_hash40);
-        }
-
-        if !dirs.contains_key(&dir_hash40) {
-            dirs.insert(dir_hash40, HashSet::new());
-        }
-
         self.stems.insert(dir_hash40, dir.rsplit("/").nth(0).unwrap());
         self.stream_paths.insert(dir_hash40, dir);
         self.files.insert(dir_hash40, ArcFileInfo::Directory);
 
+        self.dir_children.entry(parent_hash40).or_default();
+        if dir_hash40 != 0 {
+            self.add_child(parent_hash40, dir_hash40);
+        }
+        self.dir_children.entry(dir_hash40).or_default();
+
         dir_hash40
     }
 
@@ -355,18 +575,15 @@ impl<'a> Arc {
         last_hash
     }
 
-    fn load_stream_files(&mut self, arc: &ArcInternal) {
-        self.dir_children.insert(0, HashSet::new());
-        self.names.insert(0, "");
-        self.stems.insert(0, "");
-        self.files.insert(0, ArcFileInfo::Directory);
-        for stream_file in &arc.stream_entries {
+    fn load_stream_files(&mut self) {
+        for i in 0..self.tables.stream_entries.len() {
+            let stream_file = self.tables.stream_entries[i];
             let hash40 = stream_file.hash as u64 + ((stream_file.name_length as u64) << 32);
             if let Some(path) = self.get_name(hash40) {
                 let path_components = path.split('/').collect();
                 let last = self.add_dirs(path, &path_components);
-                let stream_offset_entry = arc.stream_offset_entries[
-                    arc.stream_file_indices[stream_file.index as usize] as usize
+                let stream_offset_entry = self.tables.stream_offset_entries[
+                    self.tables.stream_file_indices[stream_file.index as usize] as usize
                 ];
                 let (offset, size) = (stream_offset_entry.offset as usize, stream_offset_entry.size as usize);
                 self.files.insert(
@@ -376,21 +593,19 @@ impl<'a> Arc {
                         flags: stream_file.flags,
                     }
                 );
-                self.dir_children
-                    .get_mut(&last)
-                    .unwrap()
-                    .insert(hash40);
                 self.stems.insert(
                     hash40,
                     path_components.last().unwrap()
                 );
+                self.add_child(last, hash40);
             } else {
-                println!("Warning: hash 0x{:X} not found", hash40);
+                warn!(target: "parser", "Stream hash 0x{:X} not found", hash40);
             }
         }
     }
 
-    fn get_file_compressed(arc: &ArcInternal, file_info: &FileInfo2) -> (FileSlice<u8>, u64) {
+    /// Get the sub index holding the location of the data of `file_info`, following redirects
+    fn get_sub_index(arc: &ArcInternal, file_info: &FileInfo2) -> FileInfoSubIndex {
         let file_index = arc.file_info_indices[file_info.hash_index_2 as usize];
         let file_info = if file_info.flags & REDIRECT != 0 {
             &arc.file_infos_v2[file_index.file_info_index as usize]
@@ -398,8 +613,44 @@ impl<'a> Arc {
             file_info
         };
 
-        let sub_index = arc.file_info_sub_index[file_info.sub_file_index as usize];
-        
+        arc.file_info_sub_index[file_info.sub_file_index as usize]
+    }
+
+    /// Indices into `sub_files` of the data of every directory in `folder_offsets`
+    fn folder_sub_files(&self) -> Vec<Vec<usize>> {
+        folder_sub_files(&self.tables)
+    }
+
+    /// Region of every `FileInfo2` row, numbering the rows sharing a path in table order.
+    /// `None` for files with a single row
+    fn row_regions(&self) -> Vec<Option<usize>> {
+        let tables = &self.tables;
+        let row_hash40 = |file_info: &FileInfo2| tables.file_info_paths[file_info.hash_index as usize].path.hash40();
+        let mut row_counts: HashMap<u64, usize> = HashMap::new();
+        for file_info in &tables.file_infos_v2 {
+            *row_counts.entry(row_hash40(file_info)).or_default() += 1;
+        }
+
+        let mut regions: HashMap<u64, usize> = HashMap::new();
+        tables.file_infos_v2
+            .iter()
+            .map(|file_info| {
+                let hash40 = row_hash40(file_info);
+                if row_counts[&hash40] > 1 {
+                    let region = regions.entry(hash40).or_insert(0);
+                    *region += 1;
+                    Some(*region - 1)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Data, decompressed size and `SubFileInfo` flags of a file
+    fn get_file_compressed(arc: &ArcInternal, file_info: &FileInfo2) -> (FileSlice<u8>, u64, u32) {
+        let sub_index = Arc::get_sub_index(arc, file_info);
+
         let sub_file = arc.sub_files[sub_index.sub_file_index as usize];
         let dir_offset = arc.folder_offsets[sub_index.folder_offset_index as usize];
 
@@ -407,95 +658,279 @@ impl<'a> Arc {
                         dir_offset.offset as usize +
                         ((sub_file.offset as usize) << 2);
 
-        (FileSlice::new(offset, sub_file.comp_size as usize), sub_file.decomp_size as u64)
+        (FileSlice::new(offset, sub_file.comp_size as usize), sub_file.decomp_size as u64, sub_file.flags)
     }
 
-    fn load_compressed_files(&mut self, arc: &ArcInternal) {
-        let file_infos: Vec<_> =
-            arc.file_infos_v2
-                .par_iter()
-                .filter_map(|file_info|{
-                    let path = arc.file_info_paths[file_info.hash_index as usize];
-                    let file_hash40 = path.path.hash40();
-                    let path_string = self.get_name(file_hash40)?;
-                    let path_components: Vec<_> = path_string.split('/').collect();
-                    let (data, decomp_size) = Arc::get_file_compressed(arc, file_info);
+    fn load_directories(&mut self) {
+        self.load_directory_nodes();
+        for index in 0..self.tables.directories.len() {
+            self.load_directory_contents(index);
+        }
+    }
 
-                    Some((file_hash40, path_string, path_components, data, decomp_size))
-                })
-                .collect();
-
-        let file_infos: Vec<_> =
-            file_infos
-                .into_iter()
-                .map(|(file_hash40, path_string, path_components, data, decomp_size)|{
-                    let last = self.add_dirs(path_string, &path_components);
-                    (file_hash40, path_string, path_components, data, decomp_size, last)
-                })
-                .collect();
-
-        let dir_children = Mutex::new(&mut self.dir_children);
-        let stems = Mutex::new(&mut self.stems);
-        let files = Mutex::new(&mut self.files);
-        rayon::join(
-            || rayon::join(
-                || {
-                    files
-                        .lock()
-                        .unwrap()
-                        .par_extend(
-                            file_infos
-                                .par_iter()
-                                .map(|&(hash40, .., data, decomp_size, _)|(
-                                    hash40,
-                                    ArcFileInfo::Compressed {
-                                        data, decomp_size
-                                    }
-                                ))
-                        );
-                },
-                || {
-                    stems
-                        .lock()
-                        .unwrap()
-                        .par_extend(
-                            file_infos
-                                .par_iter()
-                                .map(|&(hash40, _, ref path_components, ..)| {(
-                                    hash40,
-                                    *path_components.last().unwrap()
-                                )})
-                        );
-                }
-            ),
-            || {
-                file_infos
-                    .par_iter()
-                    .for_each(|&(hash40, .., last )|{
-                        dir_children
-                            .lock()
-                            .unwrap()
-                            .get_mut(&last)
-                            .unwrap()
-                            .insert(hash40);
-                    });
+    /// Add every directory to the tree without loading the files they contain
+    fn load_directory_nodes(&mut self) {
+        for dir in &self.tables.directories {
+            let dir_hash40 = dir.hash40();
+            let stem = self.get_stem(dir.name.hash40(), dir_hash40);
+            self.stems.insert(dir_hash40, stem);
+            self.files.insert(dir_hash40, ArcFileInfo::Directory);
+            self.dir_children.entry(dir_hash40).or_default();
+        }
+
+        // Directories only list their children, so the parent links are needed to attach
+        // top level directories (and directories whose parent isn't in the table) to the tree
+        for index in 0..self.tables.directories.len() {
+            let dir = self.tables.directories[index];
+            self.add_to_parent(dir.parent.hash40(), dir.hash40());
+        }
+    }
+
+    /// Load the files and child directories of `self.tables.directories[index]`
+    fn load_directory_contents(&mut self, index: usize) {
+        let dir = self.tables.directories[index];
+        let dir_hash40 = dir.hash40();
+        self.dir_children.entry(dir_hash40).or_default();
+
+        let start = dir.child_dir_start_index as usize;
+        let end = start + dir.child_dir_count as usize;
+        let child_dirs: Vec<u64> = self.tables.folder_child_hashes[start..end]
+            .iter()
+            .map(|child| child.hash40())
+            .filter(|child| self.files.contains_key(child))
+            .collect();
+        for child in child_dirs {
+            self.add_child(dir_hash40, child);
+        }
+
+        let start = dir.file_name_start_index as usize;
+        let end = start + dir.file_info_count as usize;
+        let files: Vec<_> = self.tables.file_infos_v2[start..end]
+            .iter()
+            .map(|file_info| {
+                let path = self.tables.file_info_paths[file_info.hash_index as usize];
+                let file_hash40 = path.path.hash40();
+                let (data, decomp_size, flags) = Arc::get_file_compressed(&self.tables, file_info);
+                let stem = self.get_stem(path.file_name.hash40(), file_hash40);
+                (file_hash40, stem, ArcFileInfo::Compressed { data, decomp_size, flags })
+            })
+            .collect();
+        for (file_hash40, stem, file) in files {
+            self.stems.insert(file_hash40, stem);
+            self.files.insert(file_hash40, file);
+            self.add_child(dir_hash40, file_hash40);
+        }
+    }
+
+    /// Insert `child` into the children of the directory `parent`, creating `parent` if it
+    /// isn't already part of the tree
+    fn add_to_parent(&mut self, parent: u64, child: u64) {
+        if parent == child {
+            return
+        }
+
+        if let Entry::Vacant(entry) = self.dir_children.entry(parent) {
+            entry.insert(HashSet::new());
+            let stem = self.get_stem(parent, parent);
+            self.stems.insert(parent, stem);
+            self.files.insert(parent, ArcFileInfo::Directory);
+
+            // Without a label the parent's parent can't be known, so it goes under the root
+            let grandparent = self.names.get(&parent)
+                .and_then(|path| path.rfind('/').map(|i| hash40(&path[..i])))
+                .unwrap_or(0);
+            self.add_to_parent(grandparent, parent);
+        }
+
+        self.add_child(parent, child);
+    }
+
+    /// Insert `child` into the children of `parent`, the stem of `child` has to be known
+    fn add_child(&mut self, parent: u64, child: u64) {
+        self.dir_children.entry(parent).or_default().insert(child);
+        if let Some(&stem) = self.stems.get(&child) {
+            self.child_names.entry(parent).or_default().insert(stem, child);
+        }
+    }
+
+    /// Rebuild `child_names` from `dir_children`
+    fn index_child_names(&mut self) {
+        self.child_names = self.dir_children
+            .iter()
+            .map(|(&dir, children)| {
+                let names = children
+                    .iter()
+                    .filter_map(|child| Some((*self.stems.get(child)?, *child)))
+                    .collect();
+                (dir, names)
+            })
+            .collect();
+    }
+
+    /// Get the name of a single path component, falling back on the last component of the
+    /// full path's label and then on the hash itself
+    fn get_stem(&self, name_hash40: u64, path_hash40: u64) -> ArcStr {
+        if let Some(name) = self.names.get(&name_hash40) {
+            name
+        } else if let Some(path) = self.names.get(&path_hash40) {
+            path.rsplit('/').nth(0).unwrap()
+        } else {
+            Box::leak(format!("0x{:010x}", name_hash40).into_boxed_str())
+        }
+    }
+
+    /// Find a file or directory by its path, using the same names as the mount
+    pub fn get_by_path(&self, path: &str) -> Option<u64> {
+        path.split('/')
+            .filter(|name| !name.is_empty())
+            .try_fold(0, |dir, name| self.get_child(dir, name))
+    }
+
+    /// Find a child of the directory `parent` by name
+    pub fn get_child(&self, parent: u64, name: &str) -> Option<u64> {
+        self.child_names.get(&parent)?.get(name).copied()
+    }
+}
+
+/// Indices into `sub_files` of the data of every directory in `folder_offsets`.
+/// Directories list their sub files, the sub indices cover any they might not
+fn folder_sub_files(tables: &ArcInternal) -> Vec<Vec<usize>> {
+    let mut folder_sub_files = vec![Vec::new(); tables.folder_offsets.len()];
+    let mut listed = vec![false; tables.sub_files.len()];
+    for (i, folder) in tables.folder_offsets.iter().enumerate() {
+        let start = folder.sub_data_start_index as usize;
+        let end = usize::min(start + folder.sub_data_count as usize, listed.len());
+        for (index, listed) in listed.iter_mut().enumerate().take(end).skip(start) {
+            if !*listed {
+                *listed = true;
+                folder_sub_files[i].push(index);
             }
-        );
+        }
     }
+    for sub_index in &tables.file_info_sub_index {
+        let index = sub_index.sub_file_index as usize;
+        if index < listed.len() && !listed[index] {
+            listed[index] = true;
+            folder_sub_files[sub_index.folder_offset_index as usize].push(index);
+        }
+    }
+
+    folder_sub_files
 }
 
 const REDIRECT: u32 = 0x00000010;
 
-pub enum FileSliceOrVec {
-    FileSlice(FileSlice<u8>),
+/// `SubFileInfo` flags
+pub const SUB_FILE_COMPRESSED: u32 = 0x1;
+pub const SUB_FILE_ZSTD: u32 = 0x2;
+
+/// Data of a file, borrowed from the arc's map or decompressed
+pub enum FileSliceOrVec<'a> {
+    Slice(&'a [u8]),
     Vec(Vec<u8>)
 }
 
-impl FileSliceOrVec {
+impl FileSliceOrVec<'_> {
     pub fn get_slice(&self) -> &[u8] {
         match self {
-            FileSliceOrVec::FileSlice(file_slice) => &file_slice,
+            FileSliceOrVec::Slice(slice) => slice,
             FileSliceOrVec::Vec(vec) => &vec
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compression_flags() {
+        assert_eq!(Compression::from_flags(0), Compression::None);
+        // Regional and other bits don't matter
+        assert_eq!(Compression::from_flags(0x8000_0000), Compression::None);
+        assert_eq!(Compression::from_flags(SUB_FILE_COMPRESSED | SUB_FILE_ZSTD), Compression::Zstd);
+        assert_eq!(Compression::from_flags(SUB_FILE_COMPRESSED), Compression::Unknown(SUB_FILE_COMPRESSED));
+    }
+
+    #[test]
+    fn decompress_by_flags() {
+        let data = b"fighter/mario/model/body/c00/model.numdlb".repeat(8);
+        let stored = zstd::encode_all(&data[..], 0).unwrap();
+        assert_eq!(&*decompress_data(&stored, Compression::Zstd).unwrap(), &data[..]);
+
+        // Stored as is, even where the data happens to be a valid zstd frame
+        assert_eq!(&*decompress_data(&stored, Compression::None).unwrap(), &stored[..]);
+
+        let err = decompress_data(&stored, Compression::Unknown(SUB_FILE_COMPRESSED)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(decompress_data(b"not zstd", Compression::Zstd).is_err());
+    }
+
+    #[test]
+    fn tree() {
+        let arc = fixture::sample().open("tree");
+
+        let model = arc.get_by_path("fighter/mario/model.numdlb").unwrap();
+        assert_eq!(model, hash40("fighter/mario/model.numdlb"));
+        assert_eq!(arc.read_file(model).unwrap(), b"mario model ".repeat(16));
+        assert_eq!(arc.get_child(hash40("fighter/mario"), "body.nutexb"), Some(hash40("fighter/mario/body.nutexb")));
+        assert_eq!(arc.get_child(hash40("fighter"), "luigi"), Some(hash40("fighter/luigi")));
+        assert_eq!(arc.get_child(0, "ui"), Some(hash40("ui")));
+        assert_eq!(arc.get_child(hash40("fighter/mario"), "missing.numdlb"), None);
+
+        let stream = arc.get_by_path("stream:/sound/bgm/bgm_mario.nus3audio").unwrap();
+        assert_eq!(arc.read_file(stream).unwrap(), b"mario bgm");
+    }
+
+    #[test]
+    fn corrupt_file() {
+        let sample = fixture::sample();
+        let mut data = sample.build();
+        let model = hash40("fighter/mario/model.numdlb");
+        let start = match sample.open_bytes(&data, "corrupt_file_source").files[&model] {
+            ArcFileInfo::Compressed { data, flags, .. } => {
+                assert_eq!(Compression::from_flags(flags), Compression::Zstd);
+                data.inner_ptr()
+            }
+            _ => panic!("model.numdlb isn't compressed"),
+        };
+        // Break the frame's magic number
+        data[start..start + 4].copy_from_slice(&[0; 4]);
+        let arc = sample.open_bytes(&data, "corrupt_file");
+
+        // Failures aren't cached, every read fails and is counted
+        for _ in 0..2 {
+            let failures = decompression_failures();
+            let err = arc.get_file_data(model).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(decompression_failures() > failures);
+        }
+        assert!(LOGGED_FAILURES.lock().unwrap().contains(&model));
+        let failures = decompression_failures();
+        assert!(arc.stats_json()["decompression_failures"].as_u64().unwrap() >= failures);
+
+        let err = arc.get_file_data(hash40("fighter/mario/missing.numdlb")).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(arc.get_file_data(hash40("fighter/luigi/model.numdlb")).unwrap().get_slice(), &b"luigi model ".repeat(16)[..]);
+    }
+
+    #[test]
+    fn lazy_tree() {
+        let mut arc = fixture::sample().open_lazy("lazy_tree");
+        assert_eq!(arc.get_by_path("fighter"), None);
+
+        // Directories are all added on first access, their files only once they're loaded
+        arc.load_dir(0);
+        assert_eq!(arc.get_by_path("fighter/mario"), Some(hash40("fighter/mario")));
+        assert_eq!(arc.get_by_path("fighter/mario/model.numdlb"), None);
+        arc.load_dir(hash40("fighter/mario"));
+        assert_eq!(arc.get_by_path("fighter/mario/model.numdlb"), Some(hash40("fighter/mario/model.numdlb")));
+        assert_eq!(arc.get_by_path("fighter/luigi/model.numdlb"), None);
+
+        // Loading a file loads the directory it's in
+        let chara_db = hash40("ui/param/ui_chara_db.prc");
+        arc.load_file(chara_db);
+        assert_eq!(arc.get_by_path("ui/param/ui_chara_db.prc"), Some(chara_db));
+        assert_eq!(arc.read_file(chara_db).unwrap(), b"chara db");
+    }
+}
@@ -39,8 +39,10 @@ pub struct FileSystemHeader {
     pub sub_file_count_2: u32,
     pub padding: u32,
 
-    pub unk1_10: u32, // always 0x10
-    pub unk2_10: u32, // always 0x10
+    /// Both 0x10 in every known arc, `verify` reports arcs where they aren't. Nothing in the
+    /// tables depends on them
+    pub unk1_10: u32,
+    pub unk2_10: u32,
 
     pub regional_count_1: u8,
     pub regional_count_2: u8,
@@ -50,6 +52,8 @@ pub struct FileSystemHeader {
     pub extra_folder: u32,
     pub extra_count: u32,
 
+    /// No table's size or position depends on these and no invariant is known for them, so
+    /// they're only shown by `info` and written back unchanged
     pub unk: [u32; 2],
 
     pub extra_count_2: u32,
@@ -114,15 +118,6 @@ pub struct FileInformationIndex {
     pub file_info_index: u32,
 }
 
-#[repr(C)]
-#[derive(Debug, Clone, Copy)]
-pub struct SomeFolderThing {
-    pub hash: u32,
-    pub unk: u8,
-    pub index: u16,
-    pub padding: u8,
-}
-
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct Hash40 {
@@ -131,6 +126,16 @@ pub struct Hash40 {
     pub padding: [u8; 3],
 }
 
+impl Hash40 {
+    pub fn new(hash40: u64) -> Self {
+        Hash40 { hash: hash40 as u32, length: (hash40 >> 32) as u8, padding: [0; 3] }
+    }
+
+    pub fn hash40(&self) -> u64 {
+        self.hash as u64 + ((self.length as u64) << 32)
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct DirectoryInfo {
@@ -138,8 +143,8 @@ pub struct DirectoryInfo {
     pub dir_offset_index: u32,
     pub name: Hash40,
     pub parent: Hash40,
-    pub extra_dis_re: u32,
-    pub extra_dis_re_length: u32,
+    /// hash40 of another directory linked to this one, zero for directories without one
+    pub extra_dis_re: Hash40,
     pub file_name_start_index: u32,
     pub file_info_count: u32,
     pub child_dir_start_index: u32,
@@ -147,6 +152,14 @@ pub struct DirectoryInfo {
     pub flags: u32,
 }
 
+impl DirectoryInfo {
+    /// hash40 of the full directory path, the length is stored in the low byte of
+    /// `dir_offset_index`
+    pub fn hash40(&self) -> u64 {
+        self.path_hash as u64 + ((self.dir_offset_index as u64 & 0xFF) << 32)
+    }
+}
+
 #[repr(packed)]
 #[derive(Debug, Clone, Copy)]
 pub struct DirectoryOffsets {
@@ -202,13 +215,33 @@ pub struct StreamHashToName {
     pub name_index: u32,
 }
 
+/// Sizes of the tables used to find a `FileInformationPath` by the hash40 of its path
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
-pub struct FileInformationUnknownTable {
-    pub some_index: u32,
-    pub some_index_2: u32,
+pub struct FilePathLookupHeader {
+    /// Entries in the path hash to index table
+    pub path_hash_count: u32,
+    pub bucket_count: u32,
 }
 
+/// Range of the path hash to index table holding the paths whose hash40 is the index of the
+/// bucket modulo the bucket count, sorted by hash40
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FileInfoBucket {
+    pub start: u32,
+    pub count: u32,
+}
+
+impl FileInfoBucket {
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.start as usize..self.start as usize + self.count as usize
+    }
+}
+
+/// hash40 packed with a 24 bit index, the length is in the low byte of `index`. Used for the
+/// path and directory lookups and the child directory lists, where the index is of a
+/// `FileInformationPath` or a `DirectoryInfo`
 #[repr(packed)]
 #[derive(Debug, Clone, Copy)]
 pub struct HashIndexGroup {
@@ -217,9 +250,18 @@ pub struct HashIndexGroup {
 }
 
 impl HashIndexGroup {
+    pub fn new(hash40: u64, index: usize) -> Self {
+        HashIndexGroup { hash: hash40 as u32, index: ((index as u32) << 8) | (hash40 >> 32) as u32 }
+    }
+
     pub fn hash40(&self) -> u64 {
         self.hash as u64 + ((self.index as u64 & 0xFF) << 32)
     }
+
+    /// Index the group points to, stored above the length byte
+    pub fn index(&self) -> usize {
+        (self.index >> 8) as usize
+    }
 }
 
 #[cfg(test)]
@@ -240,7 +282,6 @@ mod test {
             size_of::<StreamOffsetEntry>(),
             size_of::<FileInformationPath>(),
             size_of::<FileInformationIndex>(),
-            size_of::<SomeFolderThing>(),
             size_of::<Hash40>(),
             size_of::<DirectoryInfo>(),
             size_of::<DirectoryOffsets>(),
@@ -249,7 +290,8 @@ mod test {
             size_of::<FileInfoSubIndex>(),
             size_of::<SubFileInfo>(),
             size_of::<StreamHashToName>(),
-            size_of::<FileInformationUnknownTable>(),
+            size_of::<FilePathLookupHeader>(),
+            size_of::<FileInfoBucket>(),
             size_of::<HashIndexGroup>(),
         );
     }
@@ -0,0 +1,129 @@
+use super::*;
+use std::mem::size_of;
+
+/// Where one table is in the decompressed file system table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableInfo {
+    pub name: &'static str,
+    pub offset: usize,
+    pub count: usize,
+    pub element_size: usize,
+}
+
+impl TableInfo {
+    pub fn size(&self) -> usize {
+        self.count * self.element_size
+    }
+}
+
+/// Offset of the `StreamHeader` in the decompressed table
+const STREAM_HEADER_OFFSET: usize = 0x100;
+
+/// Every table in order. The tables are contiguous, so counts come from where the next one starts
+fn table_infos(offsets: &TableOffsets) -> Vec<TableInfo> {
+    let tables = [
+        ("quick_dirs", offsets.quick_dirs, 0xC),
+        ("stream_hashes", offsets.stream_hashes, size_of::<u64>()),
+        ("stream_entries", offsets.stream_entries, 0xC),
+        ("stream_file_indices", offsets.stream_file_indices, size_of::<u32>()),
+        ("stream_offset_entries", offsets.stream_offset_entries, size_of::<StreamOffsetEntry>()),
+        ("path_lookup_header", offsets.path_lookup_header, size_of::<FilePathLookupHeader>()),
+        ("file_info_buckets", offsets.file_info_buckets, size_of::<FileInfoBucket>()),
+        ("path_hash_to_index", offsets.path_hash_to_index, size_of::<HashIndexGroup>()),
+        ("file_info_paths", offsets.file_info_paths, size_of::<FileInformationPath>()),
+        ("file_info_indices", offsets.file_info_indices, size_of::<FileInformationIndex>()),
+        ("dir_hash_to_index", offsets.dir_hash_to_index, size_of::<HashIndexGroup>()),
+        ("directories", offsets.directories, size_of::<DirectoryInfo>()),
+        ("folder_offsets", offsets.folder_offsets, size_of::<DirectoryOffsets>()),
+        ("folder_child_hashes", offsets.folder_child_hashes, size_of::<HashIndexGroup>()),
+        ("file_infos_v2", offsets.file_infos_v2, size_of::<FileInfo2>()),
+        ("file_info_sub_index", offsets.file_info_sub_index, size_of::<FileInfoSubIndex>()),
+        ("sub_files", offsets.sub_files, size_of::<SubFileInfo>()),
+    ];
+
+    let headers = [
+        TableInfo { name: "file_system_header", offset: 0, count: 1, element_size: size_of::<FileSystemHeader>() },
+        TableInfo { name: "stream_header", offset: STREAM_HEADER_OFFSET, count: 1, element_size: size_of::<StreamHeader>() },
+    ];
+    let ends = tables.iter().skip(1).map(|&(_, offset, _)| offset).chain(std::iter::once(offsets.end));
+
+    headers.iter().cloned().chain(
+        tables.iter().zip(ends).map(|(&(name, offset, element_size), end)| TableInfo {
+            name,
+            offset,
+            count: end.saturating_sub(offset) / element_size,
+            element_size,
+        })
+    ).collect()
+}
+
+impl Arc {
+    /// Offset, element count and element size of every table in the decompressed file system
+    /// table, headers first
+    pub fn table_infos(&self) -> Vec<TableInfo> {
+        table_infos(&self.tables.offsets)
+    }
+
+    /// The bytes of one table, as returned by `table_infos`
+    pub fn table_data(&self, info: &TableInfo) -> &[u8] {
+        let table = &self.tables.table;
+        let start = usize::min(info.offset, table.len());
+        &table[start..usize::min(info.offset + info.size(), table.len())]
+    }
+
+    /// Write every table to `<name>.bin` in `dir`, and the whole decompressed table to
+    /// `file_system.bin`
+    pub fn dump_tables(&self, dir: &Path) -> io::Result<Vec<TableInfo>> {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join("file_system.bin"), &self.tables.table)?;
+        let infos = self.table_infos();
+        for info in &infos {
+            std::fs::write(dir.join(format!("{}.bin", info.name)), self.table_data(info))?;
+        }
+
+        Ok(infos)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn table_counts() {
+        let mut offsets = TableOffsets { quick_dirs: 0x110, ..Default::default() };
+        offsets.stream_hashes = offsets.quick_dirs + 2 * 0xC;
+        offsets.stream_entries = offsets.stream_hashes + 3 * 8;
+        offsets.stream_file_indices = offsets.stream_entries + 3 * 0xC;
+        offsets.stream_offset_entries = offsets.stream_file_indices + 4 * 4;
+        offsets.path_lookup_header = offsets.stream_offset_entries + 4 * size_of::<StreamOffsetEntry>();
+        offsets.file_info_buckets = offsets.path_lookup_header + 8;
+        // Empty tables up to the sub files
+        offsets.path_hash_to_index = offsets.file_info_buckets;
+        offsets.file_info_paths = offsets.file_info_buckets;
+        offsets.file_info_indices = offsets.file_info_buckets;
+        offsets.dir_hash_to_index = offsets.file_info_buckets;
+        offsets.directories = offsets.file_info_buckets;
+        offsets.folder_offsets = offsets.file_info_buckets;
+        offsets.folder_child_hashes = offsets.file_info_buckets;
+        offsets.file_infos_v2 = offsets.file_info_buckets;
+        offsets.file_info_sub_index = offsets.file_info_buckets;
+        offsets.sub_files = offsets.file_info_buckets;
+        offsets.end = offsets.sub_files + 5 * size_of::<SubFileInfo>();
+
+        let infos = table_infos(&offsets);
+        let count = |name| infos.iter().find(|info| info.name == name).unwrap().count;
+        assert_eq!(infos.len(), 19);
+        assert_eq!(count("quick_dirs"), 2);
+        assert_eq!(count("stream_entries"), 3);
+        assert_eq!(count("stream_offset_entries"), 4);
+        assert_eq!(count("path_lookup_header"), 1);
+        assert_eq!(count("directories"), 0);
+        assert_eq!(count("sub_files"), 5);
+        // Contiguous, each table ends where the next starts
+        for pair in infos[2..].windows(2) {
+            assert_eq!(pair[0].offset + pair[0].size(), pair[1].offset);
+        }
+        assert_eq!(infos.last().map(|info| info.offset + info.size()), Some(offsets.end));
+    }
+}
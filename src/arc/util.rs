@@ -1,5 +1,6 @@
 use std::io::{self, Read};
 use std::slice;
+use serde::Serializer;
 
 pub fn read_struct<T, R: Read>(mut read: R) -> io::Result<T> {
     let num_bytes = ::std::mem::size_of::<T>();
@@ -15,3 +16,15 @@ pub fn read_struct<T, R: Read>(mut read: R) -> io::Result<T> {
         }
     }
 }
+
+/// Serialize a hash40 the way unlabeled names are shown
+pub fn serialize_hash40<S: Serializer>(hash40: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("0x{:010x}", hash40))
+}
+
+pub fn serialize_hash40_opt<S: Serializer>(hash40: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+    match hash40 {
+        Some(hash40) => serialize_hash40(hash40, serializer),
+        None => serializer.serialize_none(),
+    }
+}
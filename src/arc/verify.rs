@@ -0,0 +1,601 @@
+use super::*;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::mem::size_of;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProblemKind {
+    /// The data ends past the end of the arc or of its directory
+    OutOfRange { end: u64, limit: u64 },
+    /// The data partially overlaps the data of another file
+    Overlap { other: String },
+    Decompression(String),
+    SizeMismatch { expected: u64, actual: u64 },
+    HashMismatch { expected: String, actual: String },
+    /// In the saved hashes but not in the arc
+    Missing,
+    /// An entry of the file system table doesn't match what it points at
+    Table { table: &'static str, index: usize, reason: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct Problem {
+    /// Path of a file using the data, or the hash when it isn't labeled
+    pub path: String,
+    /// Offset of the data in the arc, or of the entry in the decompressed file system table
+    /// for `Table` problems
+    pub offset: u64,
+    pub kind: ProblemKind,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (0x{:x}): ", self.path, self.offset)?;
+        match &self.kind {
+            ProblemKind::OutOfRange { end, limit } => write!(f, "data ends at 0x{:x}, past 0x{:x}", end, limit),
+            ProblemKind::Overlap { other } => write!(f, "data overlaps {}", other),
+            ProblemKind::Decompression(err) => write!(f, "failed to decompress: {}", err),
+            ProblemKind::SizeMismatch { expected, actual } => {
+                write!(f, "decompressed to 0x{:x} bytes, expected 0x{:x}", actual, expected)
+            }
+            ProblemKind::HashMismatch { expected, actual } => {
+                write!(f, "sha256 is {}, expected {}", actual, expected)
+            }
+            ProblemKind::Missing => write!(f, "missing from the arc"),
+            ProblemKind::Table { table, index, reason } => write!(f, "{}[{}] {}", table, index, reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// Number of sub files and streams decompressed
+    pub checked: usize,
+    pub problems: Vec<Problem>,
+    /// sha256 of the decompressed data of every file and stream, by `hash_key`
+    pub hashes: BTreeMap<String, String>,
+}
+
+/// The stored data of one `SubFileInfo` or stream, and every file using it
+struct DataRange {
+    /// hash40 of every file using the data, with the region of the row for files with a
+    /// `FileInfo2` row per region
+    files: Vec<(u64, Option<usize>)>,
+    offset: u64,
+    comp_size: u64,
+    decomp_size: u64,
+    compression: Compression,
+    /// End of the directory the data is in, or of the arc for streams
+    limit: u64,
+}
+
+/// Problems found checking a `DataRange`, and the hashes of the files using it
+type RangeResult = (Vec<Problem>, Vec<(String, String)>);
+
+impl Arc {
+    fn data_ranges(&self) -> Vec<DataRange> {
+        let tables = &self.tables;
+        let file_section_offset = tables.arc_header.file_section_offset;
+        let arc_end = self.map.len() as u64;
+
+        let mut sub_file_users: HashMap<usize, Vec<(u64, Option<usize>)>> = HashMap::new();
+        for (file_info, region) in tables.file_infos_v2.iter().zip(self.row_regions()) {
+            let hash40 = tables.file_info_paths[file_info.hash_index as usize].path.hash40();
+            sub_file_users
+                .entry(Arc::get_sub_index(tables, file_info).sub_file_index as usize)
+                .or_default()
+                .push((hash40, region));
+        }
+
+        let mut ranges = Vec::with_capacity(tables.sub_files.len() + tables.stream_offset_entries.len());
+        for (folder, sub_files) in tables.folder_offsets.iter().zip(self.folder_sub_files()) {
+            let folder_start = file_section_offset + folder.offset;
+            for index in sub_files {
+                let sub_file = tables.sub_files[index];
+                ranges.push(DataRange {
+                    files: sub_file_users.remove(&index).unwrap_or_default(),
+                    offset: folder_start + ((sub_file.offset as u64) << 2),
+                    comp_size: sub_file.comp_size as u64,
+                    decomp_size: sub_file.decomp_size as u64,
+                    compression: Compression::from_flags(sub_file.flags),
+                    limit: u64::min(folder_start + folder.size as u64, arc_end),
+                });
+            }
+        }
+
+        let mut stream_users: HashMap<usize, Vec<(u64, Option<usize>)>> = HashMap::new();
+        for entry in &tables.stream_entries {
+            let hash40 = entry.hash as u64 + ((entry.name_length as u64) << 32);
+            stream_users.entry(tables.stream_file_indices[entry.index as usize] as usize).or_default().push((hash40, None));
+        }
+        for (index, entry) in tables.stream_offset_entries.iter().enumerate() {
+            ranges.push(DataRange {
+                files: stream_users.remove(&index).unwrap_or_default(),
+                offset: entry.offset,
+                comp_size: entry.size,
+                decomp_size: entry.size,
+                compression: Compression::None,
+                limit: arc_end,
+            });
+        }
+
+        ranges
+    }
+
+    fn range_name(&self, range: &DataRange) -> String {
+        match range.files.first() {
+            Some(&(hash40, region)) => self.hash_key(hash40, region),
+            None => format!("unused data at 0x{:x}", range.offset),
+        }
+    }
+
+    /// Key of a file's hash: its path, followed by `@<region>` for files with a row per region
+    fn hash_key(&self, hash40: u64, region: Option<usize>) -> String {
+        match region {
+            Some(region) => format!("{}@{}", self.display_path(hash40), region),
+            None => self.display_path(hash40),
+        }
+    }
+
+    /// Decompress every sub file and stream, checking their sizes and that their data is in
+    /// bounds and doesn't partially overlap other data. With `expected` (sha256 by path and
+    /// region, as written by `write_hashes`), the hashes of the decompressed data are compared too
+    pub fn verify(&self, hash: bool, expected: Option<&BTreeMap<String, String>>) -> VerifyReport {
+        let ranges = self.data_ranges();
+        let hash = hash || expected.is_some();
+
+        let results: Vec<RangeResult> = ranges
+            .par_iter()
+            .map(|range| {
+                let problem = |kind| Problem { path: self.range_name(range), offset: range.offset, kind };
+                let end = range.offset + range.comp_size;
+                if end > range.limit {
+                    return (vec![problem(ProblemKind::OutOfRange { end, limit: range.limit })], vec![])
+                }
+
+                let data = &self.map[range.offset as usize..end as usize];
+                let data = match decompress_data(data, range.compression) {
+                    Ok(data) => data,
+                    Err(err) => return (vec![problem(ProblemKind::Decompression(err.to_string()))], vec![]),
+                };
+                let mut problems = Vec::new();
+                if data.len() as u64 != range.decomp_size {
+                    problems.push(problem(ProblemKind::SizeMismatch {
+                        expected: range.decomp_size,
+                        actual: data.len() as u64,
+                    }));
+                }
+
+                let mut hashes = Vec::new();
+                if hash {
+                    let sha = format!("{:x}", Sha256::digest(&data));
+                    for &(hash40, region) in &range.files {
+                        let path = self.hash_key(hash40, region);
+                        match expected.and_then(|expected| expected.get(&path)) {
+                            Some(expected) if *expected != sha => problems.push(Problem {
+                                path: path.clone(),
+                                offset: range.offset,
+                                kind: ProblemKind::HashMismatch { expected: expected.clone(), actual: sha.clone() },
+                            }),
+                            _ => {}
+                        }
+                        hashes.push((path, sha.clone()));
+                    }
+                }
+
+                (problems, hashes)
+            })
+            .collect();
+
+        let mut report = VerifyReport { checked: ranges.len(), ..VerifyReport::default() };
+        for (problems, hashes) in results {
+            report.problems.extend(problems);
+            report.hashes.extend(hashes);
+        }
+        if let Some(expected) = expected {
+            let missing = expected
+                .keys()
+                .filter(|path| !report.hashes.contains_key(*path))
+                .map(|path| Problem { path: path.clone(), offset: 0, kind: ProblemKind::Missing });
+            report.problems.extend(missing.collect::<Vec<_>>());
+        }
+        report.problems.extend(self.overlaps(&ranges));
+        report.problems.extend(self.table_problems());
+
+        report
+    }
+
+    /// Entries of the lookup and directory tables that don't match what they point at. This is
+    /// also what confirms how the tables were decoded when run on a real arc
+    fn table_problems(&self) -> Vec<Problem> {
+        let tables = &self.tables;
+        let offsets = &tables.offsets;
+        let mut problems = Vec::new();
+        let mut report = |path: String, table, offset: usize, index, reason: String| problems.push(Problem {
+            path,
+            offset: offset as u64,
+            kind: ProblemKind::Table { table, index, reason },
+        });
+        let group_size = size_of::<HashIndexGroup>();
+
+        // The buckets split path_hash_to_index in order, each holding the paths whose hash40
+        // modulo the bucket count is its index, sorted by hash40
+        let buckets = &tables.file_info_buckets;
+        let path_hashes = &tables.path_hash_to_index;
+        let mut end = 0;
+        for (b, bucket) in buckets.iter().enumerate() {
+            let offset = offsets.file_info_buckets + b * size_of::<FileInfoBucket>();
+            let range = bucket.range();
+            if range.start != end || range.end > path_hashes.len() {
+                let reason = format!(
+                    "covers {:?}, expected it to start at {} and end by {}", range, end, path_hashes.len()
+                );
+                report(format!("bucket {}", b), "file_info_buckets", offset, b, reason);
+                end = range.end;
+                continue
+            }
+            end = range.end;
+            for i in range.clone() {
+                let hash40 = path_hashes[i].hash40();
+                let offset = offsets.path_hash_to_index + i * group_size;
+                if hash40 % buckets.len() as u64 != b as u64 {
+                    let reason = format!("is in bucket {} instead of {}", b, hash40 % buckets.len() as u64);
+                    report(self.display_path(hash40), "path_hash_to_index", offset, i, reason);
+                }
+                if i > range.start && path_hashes[i - 1].hash40() >= hash40 {
+                    let reason = String::from("isn't sorted after the previous entry of its bucket");
+                    report(self.display_path(hash40), "path_hash_to_index", offset, i, reason);
+                }
+            }
+        }
+        for (i, group) in path_hashes.iter().enumerate() {
+            let target = tables.file_info_paths.get(group.index()).map(|path| path.path.hash40());
+            if target != Some(group.hash40()) {
+                let offset = offsets.path_hash_to_index + i * group_size;
+                let reason = format!("points at FileInformationPath {} of another path", group.index());
+                report(self.display_path(group.hash40()), "path_hash_to_index", offset, i, reason);
+            }
+        }
+        for (i, path) in tables.file_info_paths.iter().enumerate() {
+            if self.file_path_index(path.path.hash40()) != Some(i) {
+                let offset = offsets.file_info_paths + i * size_of::<FileInformationPath>();
+                let reason = String::from("isn't found through its bucket");
+                report(self.display_path(path.path.hash40()), "file_info_paths", offset, i, reason);
+            }
+        }
+
+        // Directories are found through dir_hash_to_index, sorted by hash40
+        for (i, group) in tables.dir_hash_to_index.iter().enumerate() {
+            let offset = offsets.dir_hash_to_index + i * group_size;
+            if i > 0 && tables.dir_hash_to_index[i - 1].hash40() >= group.hash40() {
+                let reason = String::from("isn't sorted after the previous entry");
+                report(self.display_path(group.hash40()), "dir_hash_to_index", offset, i, reason);
+            }
+            if tables.directories.get(group.index()).map(DirectoryInfo::hash40) != Some(group.hash40()) {
+                let reason = format!("points at DirectoryInfo {} of another directory", group.index());
+                report(self.display_path(group.hash40()), "dir_hash_to_index", offset, i, reason);
+            }
+        }
+
+        // Child directory lists point at directories whose parent is the directory listing them,
+        // extra_dis_re at another directory
+        for (d, dir) in tables.directories.iter().enumerate() {
+            let dir_offset = offsets.directories + d * size_of::<DirectoryInfo>();
+            let start = dir.child_dir_start_index as usize;
+            let children = start..start + dir.child_dir_count as usize;
+            if children.end > tables.folder_child_hashes.len() {
+                let reason = format!("has child directories {:?}, past the end of folder_child_hashes", children);
+                report(self.display_path(dir.hash40()), "directories", dir_offset, d, reason);
+            } else {
+                for i in children {
+                    let child = tables.folder_child_hashes[i];
+                    let offset = offsets.folder_child_hashes + i * group_size;
+                    let reason = match tables.directories.get(child.index()) {
+                        Some(child_dir) if child_dir.hash40() != child.hash40() => {
+                            format!("points at DirectoryInfo {} of another directory", child.index())
+                        }
+                        Some(child_dir) if child_dir.parent.hash40() != dir.hash40() => format!(
+                            "is a child of {}, not {}",
+                            self.display_path(child_dir.parent.hash40()), self.display_path(dir.hash40())
+                        ),
+                        Some(_) => continue,
+                        None => format!("points at DirectoryInfo {}, past the end of the table", child.index()),
+                    };
+                    report(self.display_path(child.hash40()), "folder_child_hashes", offset, i, reason);
+                }
+            }
+
+            let extra = dir.extra_dis_re.hash40();
+            if extra != 0 && self.directory_index(extra).is_none() {
+                let reason = format!("extra_dis_re {} isn't a directory", self.display_path(extra));
+                report(self.display_path(dir.hash40()), "directories", dir_offset, d, reason);
+            }
+        }
+
+        let (unk1_10, unk2_10) = (tables.fs_header.unk1_10, tables.fs_header.unk2_10);
+        if (unk1_10, unk2_10) != (0x10, 0x10) {
+            let reason = format!("unk1_10 and unk2_10 are 0x{:x} and 0x{:x}, not 0x10", unk1_10, unk2_10);
+            report(String::from("file system header"), "file_system_header", 0, 0, reason);
+        }
+
+        problems
+    }
+
+    /// Data partially overlapping other data. Files sharing the exact same data are fine
+    fn overlaps(&self, ranges: &[DataRange]) -> Vec<Problem> {
+        let mut sorted: Vec<&DataRange> = ranges.iter().filter(|range| range.comp_size != 0).collect();
+        sorted.sort_by_key(|range| (range.offset, range.comp_size));
+        sorted.dedup_by_key(|range| (range.offset, range.comp_size));
+
+        let mut problems = Vec::new();
+        let mut furthest: Option<&DataRange> = None;
+        for range in sorted {
+            if let Some(previous) = furthest {
+                let previous_end = previous.offset + previous.comp_size;
+                if range.offset < previous_end {
+                    problems.push(Problem {
+                        path: self.range_name(range),
+                        offset: range.offset,
+                        kind: ProblemKind::Overlap { other: self.range_name(previous) },
+                    });
+                }
+                if range.offset + range.comp_size <= previous_end {
+                    continue
+                }
+            }
+            furthest = Some(range);
+        }
+
+        problems
+    }
+}
+
+/// Write hashes in the same format as sha256sum
+pub fn write_hashes<W: Write>(mut writer: W, hashes: &BTreeMap<String, String>) -> io::Result<()> {
+    for (path, sha) in hashes {
+        writeln!(writer, "{}  {}", sha, path)?;
+    }
+
+    Ok(())
+}
+
+pub fn read_hashes<R: io::Read>(reader: R) -> io::Result<BTreeMap<String, String>> {
+    BufReader::new(reader)
+        .lines()
+        .filter(|line| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+        .map(|line| {
+            let line = line?;
+            let mut split = line.splitn(2, "  ");
+            match (split.next(), split.next()) {
+                (Some(sha), Some(path)) => Ok((path.to_owned(), sha.to_owned())),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid hash line: {}", line))),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hashes_round_trip() {
+        let mut hashes = BTreeMap::new();
+        hashes.insert(String::from("fighter/mario/model.numdlb"), format!("{:x}", Sha256::digest(b"model")));
+        hashes.insert(String::from("0x123456789a"), format!("{:x}", Sha256::digest(b"")));
+
+        let mut written = Vec::new();
+        write_hashes(&mut written, &hashes).unwrap();
+        assert_eq!(read_hashes(&written[..]).unwrap(), hashes);
+        assert!(read_hashes(&b"not a hash line"[..]).is_err());
+    }
+
+    /// Index into `sub_files` of the data of `path`
+    fn sub_file(arc: &Arc, path: &str) -> usize {
+        sub_index(arc, path).sub_file_index as usize
+    }
+
+    fn sub_index(arc: &Arc, path: &str) -> FileInfoSubIndex {
+        let tables = &arc.tables;
+        let file_info = tables.file_infos_v2
+            .iter()
+            .find(|file_info| tables.file_info_paths[file_info.hash_index as usize].path.hash40() == hash40(path))
+            .unwrap();
+        Arc::get_sub_index(tables, file_info)
+    }
+
+    fn problems(arc: &Arc) -> Vec<(String, ProblemKind)> {
+        arc.verify(false, None).problems.into_iter().map(|problem| (problem.path, problem.kind)).collect()
+    }
+
+    // The broken tables are edited after parsing, as a corrupt file system table would parse
+
+    #[test]
+    fn out_of_range() {
+        let mut arc = fixture::sample().open("verify-range");
+        assert_eq!(problems(&arc), vec![]);
+
+        let index = sub_file(&arc, "ui/param/ui_chara_db.prc");
+        let tables = &mut arc.tables;
+        let folder = *tables.folder_offsets.last().unwrap();
+        let folder_start = tables.arc_header.file_section_offset + folder.offset;
+        tables.sub_files[index].comp_size += 0x100;
+        let sub_file = tables.sub_files[index];
+        let end = folder_start + ((sub_file.offset as u64) << 2) + sub_file.comp_size as u64;
+        assert_eq!(problems(&arc), vec![(
+            String::from("ui/param/ui_chara_db.prc"),
+            ProblemKind::OutOfRange { end, limit: folder_start + folder.size as u64 },
+        )]);
+    }
+
+    #[test]
+    fn partial_overlap() {
+        let mut arc = fixture::sample().open("verify-overlap");
+        let model = sub_file(&arc, "fighter/mario/model.numdlb");
+        let body = sub_file(&arc, "fighter/mario/body.nutexb");
+        arc.tables.sub_files[body].offset = arc.tables.sub_files[model].offset + 1;
+        assert_eq!(problems(&arc), vec![(
+            String::from("fighter/mario/body.nutexb"),
+            ProblemKind::Overlap { other: String::from("fighter/mario/model.numdlb") },
+        )]);
+    }
+
+    #[test]
+    fn size_mismatch() {
+        let mut arc = fixture::sample().open("verify-size");
+        let body = sub_file(&arc, "fighter/mario/body.nutexb");
+        arc.tables.sub_files[body].decomp_size = 5;
+        assert_eq!(problems(&arc), vec![(
+            String::from("fighter/mario/body.nutexb"),
+            ProblemKind::SizeMismatch { expected: 5, actual: b"mario texture".len() as u64 },
+        )]);
+    }
+
+    #[test]
+    fn bad_zstd_frame() {
+        let sample = fixture::sample();
+        let mut data = sample.build();
+        let arc = sample.open_bytes(&data, "verify-zstd-source");
+        let tables = &arc.tables;
+        let sub_index = sub_index(&arc, "fighter/mario/model.numdlb");
+        let sub_file = tables.sub_files[sub_index.sub_file_index as usize];
+        assert_eq!(Compression::from_flags(sub_file.flags), Compression::Zstd);
+        let folder = tables.folder_offsets[sub_index.folder_offset_index as usize];
+        let start = (tables.arc_header.file_section_offset + folder.offset) as usize + ((sub_file.offset as usize) << 2);
+        // Break the frame's magic number
+        data[start..start + 4].copy_from_slice(&[0; 4]);
+
+        let problems = problems(&sample.open_bytes(&data, "verify-zstd"));
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].0, "fighter/mario/model.numdlb");
+        assert!(matches!(problems[0].1, ProblemKind::Decompression(_)), "{:?}", problems[0].1);
+    }
+
+    #[test]
+    fn regional_hashes() {
+        let arc = fixture::sample()
+            .regional_file("ui/param/ui_msg.msbt", &[b"us message", b"jp message"])
+            .open("verify-regional");
+        let report = arc.verify(true, None);
+        assert_eq!(report.problems.len(), 0);
+        let sha = |data: &[u8]| format!("{:x}", Sha256::digest(data));
+        assert_eq!(report.hashes["ui/param/ui_msg.msbt@0"], sha(b"us message"));
+        assert_eq!(report.hashes["ui/param/ui_msg.msbt@1"], sha(b"jp message"));
+        assert_eq!(report.hashes["fighter/mario/body.nutexb"], sha(b"mario texture"));
+        assert!(!report.hashes.contains_key("ui/param/ui_msg.msbt"));
+
+        // Every region matches its own saved hash
+        let mut saved = Vec::new();
+        write_hashes(&mut saved, &report.hashes).unwrap();
+        let mut expected = read_hashes(&saved[..]).unwrap();
+        assert!(arc.verify(false, Some(&expected)).problems.is_empty());
+
+        expected.insert(String::from("ui/param/ui_msg.msbt@1"), sha(b"us message"));
+        expected.insert(String::from("ui/param/ui_msg.msbt@2"), sha(b"eu message"));
+        let problems: Vec<_> = arc.verify(false, Some(&expected))
+            .problems
+            .into_iter()
+            .map(|problem| (problem.path, problem.kind))
+            .collect();
+        assert_eq!(problems, vec![
+            (String::from("ui/param/ui_msg.msbt@1"), ProblemKind::HashMismatch {
+                expected: sha(b"us message"),
+                actual: sha(b"jp message"),
+            }),
+            (String::from("ui/param/ui_msg.msbt@2"), ProblemKind::Missing),
+        ]);
+    }
+
+    /// `Table` problems as (path, table, index)
+    fn table_problems(arc: &Arc) -> Vec<(String, &'static str, usize)> {
+        problems(arc)
+            .into_iter()
+            .map(|(path, kind)| match kind {
+                ProblemKind::Table { table, index, .. } => (path, table, index),
+                kind => panic!("{} isn't a table problem: {:?}", path, kind),
+            })
+            .collect()
+    }
+
+    fn dir_index(arc: &Arc, path: &str) -> usize {
+        arc.directory_index(hash40(path)).unwrap()
+    }
+
+    #[test]
+    fn path_buckets() {
+        let mut arc = fixture::sample().open("verify-buckets");
+        assert_eq!(arc.table_problems().len(), 0);
+
+        // With one more bucket, paths no longer are in the bucket of their hash40 modulo the
+        // bucket count
+        let end = arc.tables.path_hash_to_index.len() as u32;
+        arc.tables.file_info_buckets.push(FileInfoBucket { start: end, count: 0 });
+        let tables = &arc.tables;
+        let mut expected = Vec::new();
+        for (b, bucket) in tables.file_info_buckets.iter().enumerate() {
+            for i in bucket.range() {
+                let hash40 = tables.path_hash_to_index[i].hash40();
+                if hash40 % 4 != b as u64 {
+                    expected.push((arc.display_path(hash40), "path_hash_to_index", i));
+                }
+            }
+        }
+        let misplaced: Vec<_> = expected.iter().map(|(path, _, _)| path.clone()).collect();
+        for (i, path) in tables.file_info_paths.iter().enumerate() {
+            let path = arc.display_path(path.path.hash40());
+            if misplaced.contains(&path) {
+                expected.push((path, "file_info_paths", i));
+            }
+        }
+        assert!(!misplaced.is_empty());
+        assert_eq!(table_problems(&arc), expected);
+    }
+
+    #[test]
+    fn directory_lookup() {
+        let mut arc = fixture::sample().open("verify-dir-lookup");
+        let mario = dir_index(&arc, "fighter/mario");
+        let luigi = dir_index(&arc, "fighter/luigi");
+        let position = arc.tables.dir_hash_to_index
+            .iter()
+            .position(|group| group.hash40() == hash40("fighter/mario"))
+            .unwrap();
+        arc.tables.dir_hash_to_index[position] = HashIndexGroup::new(hash40("fighter/mario"), luigi);
+        assert_ne!(mario, luigi);
+        assert_eq!(table_problems(&arc), vec![(String::from("fighter/mario"), "dir_hash_to_index", position)]);
+    }
+
+    #[test]
+    fn child_directories() {
+        let mut arc = fixture::sample().open("verify-children");
+        let luigi = dir_index(&arc, "fighter/luigi");
+        arc.tables.directories[luigi].parent = Hash40::new(hash40("ui"));
+        let fighter = arc.tables.directories[dir_index(&arc, "fighter")];
+        let position = arc.tables.folder_child_hashes
+            .iter()
+            .position(|child| child.hash40() == hash40("fighter/luigi"))
+            .unwrap();
+        assert!((fighter.child_dir_start_index as usize..).contains(&position));
+        assert_eq!(table_problems(&arc), vec![(String::from("fighter/luigi"), "folder_child_hashes", position)]);
+    }
+
+    #[test]
+    fn extra_dis_re() {
+        let mut arc = fixture::sample().open("verify-extra-dis-re");
+        let mario = dir_index(&arc, "fighter/mario");
+        arc.tables.directories[mario].extra_dis_re = Hash40::new(hash40("fighter/luigi"));
+        assert_eq!(table_problems(&arc), vec![]);
+
+        arc.tables.directories[mario].extra_dis_re = Hash40::new(hash40("fighter/peach"));
+        assert_eq!(table_problems(&arc), vec![(String::from("fighter/mario"), "directories", mario)]);
+    }
+
+    #[test]
+    fn header_constants() {
+        let mut arc = fixture::sample().open("verify-header");
+        arc.tables.fs_header.unk2_10 = 0x20;
+        assert_eq!(table_problems(&arc), vec![(String::from("file system header"), "file_system_header", 0)]);
+    }
+}
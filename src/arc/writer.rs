@@ -0,0 +1,792 @@
+use super::*;
+use std::io::{BufWriter, Cursor, Seek, SeekFrom, Write};
+use std::mem::size_of;
+use util::read_struct;
+
+/// Alignment of everything `repack` writes
+const ALIGNMENT: u64 = 0x10;
+
+/// zstd level used for replaced files and the file system table, 0 is zstd's default
+const COMPRESSION_LEVEL: i32 = 0;
+
+pub(super) fn as_bytes<T: Copy>(value: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+pub(super) fn slice_as_bytes<T: Copy>(values: &[T]) -> &[u8] {
+    unsafe {
+        std::slice::from_raw_parts(values.as_ptr() as *const u8, std::mem::size_of_val(values))
+    }
+}
+
+/// Overwrite the table at `offset` in the decompressed file system table
+fn write_table<T: Copy>(table: &mut [u8], offset: usize, values: &[T]) {
+    let bytes = slice_as_bytes(values);
+    table[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+/// Pad to `ALIGNMENT`, returning the new position
+fn pad<W: Write>(writer: &mut W, pos: u64) -> io::Result<u64> {
+    let aligned = pos.div_ceil(ALIGNMENT) * ALIGNMENT;
+    writer.write_all(&vec![0; (aligned - pos) as usize])?;
+    Ok(aligned)
+}
+
+/// Compress `data` with zstd, keeping it uncompressed if that's smaller. Returns the stored
+/// data and its compression flags
+fn compress(data: &[u8]) -> io::Result<(Vec<u8>, u32)> {
+    let compressed = zstd::encode_all(data, COMPRESSION_LEVEL)?;
+    if compressed.len() < data.len() {
+        Ok((compressed, SUB_FILE_COMPRESSED | SUB_FILE_ZSTD))
+    } else {
+        Ok((data.to_vec(), 0))
+    }
+}
+
+/// A writer that can make sure everything written so far is on disk, so `patch` can update the
+/// header only once the data it points to is there
+pub trait SyncData: Write + Seek {
+    fn sync_data(&mut self) -> io::Result<()>;
+}
+
+impl SyncData for File {
+    fn sync_data(&mut self) -> io::Result<()> {
+        File::sync_data(self)
+    }
+}
+
+impl SyncData for BufWriter<File> {
+    fn sync_data(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.get_ref().sync_data()
+    }
+}
+
+/// In memory, there's nothing to sync
+impl SyncData for Cursor<Vec<u8>> {
+    fn sync_data(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What `repack` and `patch` write into an arc
+#[derive(Default)]
+pub struct Changes {
+    /// Decompressed data of replaced files and streams, by hash40
+    pub replaced: HashMap<u64, Vec<u8>>,
+    /// Path and decompressed data of files to add to directories already in the arc
+    pub added: Vec<(String, Vec<u8>)>,
+}
+
+/// The tables `repack` and `patch` write, with the added files, along with where the replaced
+/// data goes
+struct Edits<'a> {
+    tables: ArcInternal,
+    /// `SubFileInfo` of every entry of `tables.sub_files` before the edits, zeroed for added files
+    old_sub_files: Vec<SubFileInfo>,
+    replaced_sub_files: HashMap<usize, &'a [u8]>,
+    /// Indices into `stream_offset_entries` of replaced stream files
+    replaced_streams: Vec<(usize, &'a [u8])>,
+    /// Indices into `sub_files` of the data of every directory in `folder_offsets`
+    folder_sub_files: Vec<Vec<usize>>,
+}
+
+impl Edits<'_> {
+    fn is_modified(&self, folder: usize) -> bool {
+        self.folder_sub_files[folder].iter().any(|index| self.replaced_sub_files.contains_key(index))
+    }
+}
+
+fn to_u32(value: u64, what: &str) -> io::Result<u32> {
+    if value > u32::MAX as u64 {
+        Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} 0x{:x} doesn't fit in the arc", what, value)))
+    } else {
+        Ok(value as u32)
+    }
+}
+
+const NEW_SUB_FILE: SubFileInfo = SubFileInfo { offset: 0, comp_size: 0, decomp_size: 0, flags: 0 };
+
+/// Indices into `sub_files` of the data of every file in `hashes`
+fn sub_file_indices<T>(tables: &ArcInternal, hashes: &HashMap<u64, T>) -> HashMap<u64, Vec<usize>> {
+    let mut indices: HashMap<u64, Vec<usize>> = HashMap::new();
+    for file_info in &tables.file_infos_v2 {
+        let hash40 = tables.file_info_paths[file_info.hash_index as usize].path.hash40();
+        if hashes.contains_key(&hash40) {
+            let sub_index = Arc::get_sub_index(tables, file_info);
+            let sub_files = indices.entry(hash40).or_default();
+            if !sub_files.contains(&(sub_index.sub_file_index as usize)) {
+                sub_files.push(sub_index.sub_file_index as usize);
+            }
+        }
+    }
+
+    indices
+}
+
+/// Add the rows of a new file at `path` to the directory `dir`, with empty data for `repack` or
+/// `patch` to fill in. Its `FileInfo2` goes after the directory's files and its `SubFileInfo`
+/// after the directory's data, so every index past those is shifted
+fn add_file(tables: &mut ArcInternal, old_sub_files: &mut Vec<SubFileInfo>, path: &str, dir: usize) -> io::Result<()> {
+    let dir_info = tables.directories[dir];
+    let folder = (dir_info.dir_offset_index >> 8) as usize;
+    let row = (dir_info.file_name_start_index + dir_info.file_info_count) as usize;
+    let folder_offsets = *tables.folder_offsets.get(folder).ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("directory of {} has no data in the arc", path)
+    ))?;
+    let sub_file = (folder_offsets.sub_data_start_index + folder_offsets.sub_data_count) as usize;
+    let sub_index = tables.fs_header.file_info_sub_index_count as usize;
+    if row > tables.fs_header.file_info_count as usize ||
+       sub_file > tables.fs_header.sub_file_count as usize ||
+       sub_index > tables.file_info_sub_index.len() ||
+       tables.file_info_buckets.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the tables of the directory of {} don't leave room to add files", path)
+        ))
+    }
+
+    for sub_index in &mut tables.file_info_sub_index {
+        if sub_index.sub_file_index as usize >= sub_file {
+            sub_index.sub_file_index += 1;
+        }
+        // The top byte holds flags
+        if (sub_index.file_info_index_and_flag & 0xFFFFFF) as usize >= row {
+            sub_index.file_info_index_and_flag += 1;
+        }
+    }
+    for (i, other) in tables.folder_offsets.iter_mut().enumerate() {
+        if i != folder && other.sub_data_start_index as usize >= sub_file {
+            other.sub_data_start_index += 1;
+        }
+    }
+    for file_info in &mut tables.file_infos_v2 {
+        if file_info.sub_file_index as usize >= sub_index {
+            file_info.sub_file_index += 1;
+        }
+    }
+    for index in &mut tables.file_info_indices {
+        if index.file_info_index as usize >= row {
+            index.file_info_index += 1;
+        }
+    }
+    for (i, other) in tables.directories.iter_mut().enumerate() {
+        if i != dir && other.file_name_start_index as usize >= row {
+            other.file_name_start_index += 1;
+        }
+    }
+
+    // The parent and the directory link are copied from the directory's other files
+    let sibling = if dir_info.file_info_count > 0 {
+        let file_info = tables.file_infos_v2[row - 1];
+        Some((tables.file_info_paths[file_info.hash_index as usize], tables.file_info_indices[file_info.hash_index_2 as usize]))
+    } else {
+        None
+    };
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+    let ext = name.rsplit_once('.').map_or("", |(_, ext)| ext);
+    let path_index = tables.file_info_paths.len();
+    let file_index = tables.file_info_indices.len();
+    tables.file_info_paths.push(FileInformationPath {
+        path: HashIndexGroup::new(hash40(path), file_index),
+        ext: HashIndexGroup::new(hash40(ext), 0),
+        parent: sibling.map_or(HashIndexGroup::new(hash40(parent), 0), |(path, _)| path.parent),
+        file_name: HashIndexGroup::new(hash40(name), 0),
+    });
+    tables.file_info_indices.push(FileInformationIndex {
+        dir_offset_index: sibling.map_or(dir as u32, |(_, index)| index.dir_offset_index),
+        file_info_index: row as u32,
+    });
+    tables.file_infos_v2.insert(row, FileInfo2 {
+        hash_index: path_index as u32,
+        hash_index_2: file_index as u32,
+        sub_file_index: sub_index as u32,
+        flags: 0,
+    });
+    tables.file_info_sub_index.insert(sub_index, FileInfoSubIndex {
+        folder_offset_index: folder as u32,
+        sub_file_index: sub_file as u32,
+        file_info_index_and_flag: row as u32,
+    });
+    tables.sub_files.insert(sub_file, NEW_SUB_FILE);
+    old_sub_files.insert(sub_file, NEW_SUB_FILE);
+
+    // Paths are looked up in the bucket of their hash40, sorted by hash40
+    let bucket_count = tables.file_info_buckets.len() as u64;
+    let bucket = (hash40(path) % bucket_count) as usize;
+    let range = tables.file_info_buckets[bucket].range();
+    let position = range.start + tables.path_hash_to_index[range]
+        .partition_point(|group| group.hash40() < hash40(path));
+    tables.path_hash_to_index.insert(position, HashIndexGroup::new(hash40(path), path_index));
+    tables.file_info_buckets[bucket].count += 1;
+    for later in &mut tables.file_info_buckets[bucket + 1..] {
+        later.start += 1;
+    }
+
+    let header = &mut tables.fs_header;
+    header.file_info_path_count += 1;
+    header.file_info_index_count += 1;
+    header.file_info_count += 1;
+    header.file_info_sub_index_count += 1;
+    header.sub_file_count += 1;
+    tables.directories[dir].file_info_count += 1;
+    tables.folder_offsets[folder].sub_data_count += 1;
+
+    Ok(())
+}
+
+impl Arc {
+    /// Indices into `stream_offset_entries` of every stream file in `hashes`
+    fn stream_offset_indices<T>(&self, hashes: &HashMap<u64, T>) -> HashMap<u64, usize> {
+        let tables = &self.tables;
+        tables.stream_entries
+            .iter()
+            .filter_map(|entry| {
+                let hash40 = entry.hash as u64 + ((entry.name_length as u64) << 32);
+                if hashes.contains_key(&hash40) {
+                    Some((hash40, tables.stream_file_indices[entry.index as usize] as usize))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Fail for replaced files whose regional variants have different data, since one
+    /// replacement would overwrite every region
+    fn check_regions(&self, replaced: &HashMap<u64, Vec<u8>>) -> io::Result<()> {
+        let tables = &self.tables;
+        let mut data: HashMap<u64, HashSet<(usize, usize)>> = HashMap::new();
+        for file_info in &tables.file_infos_v2 {
+            let hash40 = tables.file_info_paths[file_info.hash_index as usize].path.hash40();
+            if replaced.contains_key(&hash40) {
+                let (slice, _, _) = Arc::get_file_compressed(tables, file_info);
+                data.entry(hash40).or_default().insert((slice.inner_ptr(), slice.len()));
+            }
+        }
+
+        let mut regional: Vec<_> = data
+            .iter()
+            .filter(|(_, data)| data.len() > 1)
+            .map(|(&hash40, _)| self.display_path(hash40))
+            .collect();
+        if regional.is_empty() {
+            return Ok(())
+        }
+        regional.sort();
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("files with different data for each region can't be replaced as one file: {}", regional.join(", "))
+        ))
+    }
+
+    fn edits<'a>(&'a self, changes: &'a Changes) -> io::Result<Edits<'a>> {
+        self.check_regions(&changes.replaced)?;
+        let mut data: HashMap<u64, &[u8]> = changes.replaced
+            .iter()
+            .map(|(&hash40, data)| (hash40, &data[..]))
+            .collect();
+
+        let mut tables = self.tables.clone();
+        let mut old_sub_files = tables.sub_files.clone();
+        for (path, file) in &changes.added {
+            let path = path.trim_start_matches('/');
+            let file_hash40 = hash40(path);
+            if self.file_path_index(file_hash40).is_some() || data.insert(file_hash40, file).is_some() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is already in the arc", path)))
+            }
+            let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+            let dir = self.directory_index(hash40(parent)).ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} isn't a directory in the arc, adding directories isn't supported", parent)
+            ))?;
+            add_file(&mut tables, &mut old_sub_files, path, dir)?;
+        }
+
+        let sub_file_indices = sub_file_indices(&tables, &data);
+        let stream_offset_indices = self.stream_offset_indices(&data);
+        let missing: Vec<_> = data
+            .keys()
+            .filter(|hash40| {
+                !sub_file_indices.contains_key(hash40) && !stream_offset_indices.contains_key(hash40)
+            })
+            .map(|hash40| format!("0x{:010x}", hash40))
+            .collect();
+        if !missing.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("files not in the arc: {}", missing.join(", "))
+            ))
+        }
+
+        let mut replaced_sub_files: HashMap<usize, &[u8]> = HashMap::new();
+        for (hash40, indices) in &sub_file_indices {
+            for &index in indices {
+                replaced_sub_files.insert(index, data[hash40]);
+            }
+        }
+        let mut replaced_streams: Vec<_> = stream_offset_indices
+            .iter()
+            .map(|(hash40, &index)| (index, data[hash40]))
+            .collect();
+        replaced_streams.sort_by_key(|&(index, _)| index);
+
+        Ok(Edits {
+            folder_sub_files: folder_sub_files(&tables),
+            tables,
+            old_sub_files,
+            replaced_sub_files,
+            replaced_streams,
+        })
+    }
+
+    /// Write the replaced stream files at `pos`, returning the position after them
+    fn write_streams<W: Write>(&self, edits: &mut Edits, writer: &mut W, mut pos: u64) -> io::Result<u64> {
+        for &(index, data) in &edits.replaced_streams {
+            pos = pad(writer, pos)?;
+            writer.write_all(data)?;
+            edits.tables.stream_offset_entries[index] = StreamOffsetEntry { size: data.len() as u64, offset: pos };
+            pos += data.len() as u64;
+        }
+
+        Ok(pos)
+    }
+
+    /// Write the data of the directory `folder` at `pos`, with its replaced and added files.
+    /// Files sharing data keep sharing it. Returns the position after the directory
+    fn write_folder<W: Write>(
+        &self,
+        edits: &mut Edits,
+        folder: usize,
+        writer: &mut W,
+        pos: u64,
+        file_section_offset: u64
+    ) -> io::Result<u64> {
+        let tables = &self.tables;
+        let old_folder = tables.folder_offsets[folder];
+        let old_start = tables.arc_header.file_section_offset as usize + old_folder.offset as usize;
+
+        let mut pos = pad(writer, pos)?;
+        let start = pos;
+        let mut written: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut decomp_size = old_folder.decomp_size as i64;
+        for &index in &edits.folder_sub_files[folder] {
+            let sub_file = edits.old_sub_files[index];
+            let key = (sub_file.offset, sub_file.comp_size);
+            if let Some(data) = edits.replaced_sub_files.get(&index) {
+                let (stored, flags) = compress(data)?;
+                pos = pad(writer, pos)?;
+                writer.write_all(&stored)?;
+                edits.tables.sub_files[index] = SubFileInfo {
+                    offset: to_u32((pos - start) >> 2, "file offset")?,
+                    comp_size: to_u32(stored.len() as u64, "compressed size")?,
+                    decomp_size: to_u32(data.len() as u64, "decompressed size")?,
+                    flags: (sub_file.flags & !(SUB_FILE_COMPRESSED | SUB_FILE_ZSTD)) | flags,
+                };
+                decomp_size += data.len() as i64 - sub_file.decomp_size as i64;
+                pos += stored.len() as u64;
+            } else if let Some(&offset) = written.get(&key) {
+                edits.tables.sub_files[index].offset = offset;
+            } else {
+                let data_start = old_start + ((sub_file.offset as usize) << 2);
+                pos = pad(writer, pos)?;
+                writer.write_all(&self.map[data_start..data_start + sub_file.comp_size as usize])?;
+                let offset = to_u32((pos - start) >> 2, "file offset")?;
+                written.insert(key, offset);
+                edits.tables.sub_files[index].offset = offset;
+                pos += sub_file.comp_size as u64;
+            }
+        }
+
+        let new_folder = &mut edits.tables.folder_offsets[folder];
+        new_folder.offset = start - file_section_offset;
+        new_folder.size = to_u32(pos - start, "directory size")?;
+        new_folder.decomp_size = to_u32(decomp_size as u64, "directory size")?;
+
+        Ok(pos)
+    }
+
+    /// Write the file system table with the edited tables at `pos`. Returns its offset and the
+    /// position after it
+    fn write_file_system<W: Write>(&self, edits: &Edits, writer: &mut W, pos: u64) -> io::Result<(u64, u64)> {
+        let old = &self.tables;
+        let tables = &edits.tables;
+
+        // The tables before the path lookup keep their size, the ones after it grow with the
+        // added files
+        let mut table = old.table[..old.offsets.path_lookup_header].to_vec();
+        write_table(&mut table, old.offsets.stream_offset_entries, &tables.stream_offset_entries);
+        table.extend_from_slice(as_bytes(&FilePathLookupHeader {
+            path_hash_count: tables.path_hash_to_index.len() as u32,
+            bucket_count: tables.file_info_buckets.len() as u32,
+        }));
+        table.extend_from_slice(slice_as_bytes(&tables.file_info_buckets));
+        table.extend_from_slice(slice_as_bytes(&tables.path_hash_to_index));
+        table.extend_from_slice(slice_as_bytes(&tables.file_info_paths));
+        table.extend_from_slice(slice_as_bytes(&tables.file_info_indices));
+        table.extend_from_slice(slice_as_bytes(&tables.dir_hash_to_index));
+        table.extend_from_slice(slice_as_bytes(&tables.directories));
+        table.extend_from_slice(slice_as_bytes(&tables.folder_offsets));
+        table.extend_from_slice(slice_as_bytes(&tables.folder_child_hashes));
+        table.extend_from_slice(slice_as_bytes(&tables.file_infos_v2));
+        table.extend_from_slice(slice_as_bytes(&tables.file_info_sub_index));
+        table.extend_from_slice(slice_as_bytes(&tables.sub_files));
+        table.extend_from_slice(&old.table[old.offsets.end..]);
+
+        let fs_header = FileSystemHeader {
+            table_filesize: to_u32(
+                tables.fs_header.table_filesize as u64 + table.len() as u64 - old.table.len() as u64,
+                "file system table size"
+            )?,
+            ..tables.fs_header
+        };
+        write_table(&mut table, 0, &[fs_header]);
+        let compressed_table = zstd::encode_all(&table[..], COMPRESSION_LEVEL)?;
+
+        let file_system = old.arc_header.file_system.inner() as usize;
+        let old_table_header: CompTableHeader = read_struct(&self.map[file_system..])?;
+        let table_header = CompTableHeader {
+            header_size: old_table_header.header_size,
+            decomp_size: table.len() as u32,
+            comp_size: compressed_table.len() as u32,
+            section_size: (old_table_header.section_size - old_table_header.comp_size) +
+                          compressed_table.len() as u32,
+        };
+        let start = pad(writer, pos)?;
+        writer.write_all(as_bytes(&table_header))?;
+        writer.write_all(&compressed_table)?;
+
+        Ok((start, start + (size_of::<CompTableHeader>() + compressed_table.len()) as u64))
+    }
+
+    /// Write a new arc to `writer` with `changes` applied. Directories without replaced or added
+    /// files and the stream data are copied unchanged, every other table only gets the rows of
+    /// the added files and the new offsets and sizes.
+    ///
+    /// Files can only be added to directories already in the arc. A replaced file whose regional
+    /// variants have different data fails with `InvalidInput`, as do replacements for hashes
+    /// that aren't in the arc.
+    pub fn repack<W: Write + Seek>(&self, changes: &Changes, writer: &mut W) -> io::Result<()> {
+        let tables = &self.tables;
+        let header = tables.arc_header;
+        let file_section_offset = header.file_section_offset as usize;
+        let mut edits = self.edits(changes)?;
+
+        // Everything before the file section (the header and stream data) is copied as is. Streams
+        // `patch` appended to the end of the arc are moved back in with the replaced ones
+        for (index, entry) in tables.stream_offset_entries.iter().enumerate() {
+            let (start, end) = (entry.offset as usize, (entry.offset + entry.size) as usize);
+            if end > file_section_offset && !edits.replaced_streams.iter().any(|&(i, _)| i == index) {
+                let data = self.map.get(start..end).ok_or_else(|| io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("stream data at 0x{:x} is past the end of the arc", start)
+                ))?;
+                edits.replaced_streams.push((index, data));
+            }
+        }
+        edits.replaced_streams.sort_by_key(|&(index, _)| index);
+        writer.seek(SeekFrom::Start(0))?;
+        writer.write_all(&self.map[..file_section_offset])?;
+
+        // Replaced streams are appended after the existing stream data
+        let pos = self.write_streams(&mut edits, writer, file_section_offset as u64)?;
+        let new_file_section_offset = pad(writer, pos)?;
+        let mut pos = new_file_section_offset;
+
+        let mut folder_order: Vec<usize> = (0..tables.folder_offsets.len()).collect();
+        folder_order.sort_by_key(|&i| tables.folder_offsets[i].offset);
+
+        let mut new_folder_starts = Vec::with_capacity(folder_order.len());
+        for i in folder_order {
+            let folder = tables.folder_offsets[i];
+            let old_start = file_section_offset + folder.offset as usize;
+            let sub_file_end = edits.folder_sub_files[i]
+                .iter()
+                .map(|&index| {
+                    let sub_file = edits.old_sub_files[index];
+                    ((sub_file.offset as usize) << 2) + sub_file.comp_size as usize
+                })
+                .max()
+                .unwrap_or(0);
+
+            pos = pad(writer, pos)?;
+            new_folder_starts.push((old_start as u64, pos));
+            if !edits.is_modified(i) && sub_file_end <= folder.size as usize {
+                writer.write_all(&self.map[old_start..old_start + folder.size as usize])?;
+                edits.tables.folder_offsets[i].offset = pos - new_file_section_offset;
+                pos += folder.size as u64;
+            } else {
+                pos = self.write_folder(&mut edits, i, writer, pos, new_file_section_offset)?;
+            }
+        }
+
+        // The shared section starts at whichever directory started it before
+        let shared_section_offset = new_folder_starts
+            .iter()
+            .filter(|(old_start, _)| *old_start >= header.shared_section_offset)
+            .min()
+            .map(|&(_, start)| start)
+            .unwrap_or(pos);
+
+        let (file_system, mut pos) = self.write_file_system(&edits, writer, pos)?;
+
+        // The section after the file system table is copied as is
+        let unk_section = header.unk_section_offset.inner();
+        let unk_section = if unk_section as usize >= file_section_offset {
+            let unk_header: CompTableHeader = read_struct(&self.map[unk_section as usize..])?;
+            let start = unk_section as usize;
+            let end = start + size_of::<CompTableHeader>() + unk_header.comp_size as usize;
+            pos = pad(writer, pos)?;
+            writer.write_all(&self.map[start..end])?;
+            pos
+        } else {
+            unk_section
+        };
+
+        let new_header = ArcHeader {
+            file_section_offset: new_file_section_offset,
+            shared_section_offset,
+            file_system: FilePtr64::new(file_system),
+            unk_section_offset: FilePtr64::new(unk_section),
+            ..header
+        };
+        writer.seek(SeekFrom::Start(0))?;
+        writer.write_all(as_bytes(&new_header))?;
+        writer.flush()
+    }
+
+    /// Apply `changes` in place, `writer` being the arc this was opened from. The directories
+    /// containing replaced or added files, replaced streams and a new file system table are
+    /// appended to the end of the arc and synced to disk, then the header is pointed at the new
+    /// table. Everything else is left untouched, so the arc stays valid if writing is
+    /// interrupted before the header is updated.
+    pub fn patch<W: SyncData>(&self, changes: &Changes, writer: &mut W) -> io::Result<()> {
+        let header = self.tables.arc_header;
+        let mut edits = self.edits(changes)?;
+
+        let pos = writer.seek(SeekFrom::End(0))?;
+        let mut pos = self.write_streams(&mut edits, writer, pos)?;
+        for i in 0..edits.tables.folder_offsets.len() {
+            if edits.is_modified(i) {
+                pos = self.write_folder(&mut edits, i, writer, pos, header.file_section_offset)?;
+            }
+        }
+        let (file_system, _) = self.write_file_system(&edits, writer, pos)?;
+        writer.sync_data()?;
+
+        let new_header = ArcHeader {
+            file_system: FilePtr64::new(file_system),
+            ..header
+        };
+        writer.seek(SeekFrom::Start(0))?;
+        writer.write_all(as_bytes(&new_header))?;
+        writer.sync_data()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fixture::TestArc;
+
+    #[test]
+    fn compress_flags() {
+        let data = vec![0x41; 0x1000];
+        let (stored, flags) = compress(&data).unwrap();
+        assert_eq!(flags, SUB_FILE_COMPRESSED | SUB_FILE_ZSTD);
+        assert_eq!(zstd::decode_all(&stored[..]).unwrap(), data);
+
+        // Too small to benefit from compression
+        let (stored, flags) = compress(b"ab").unwrap();
+        assert_eq!(flags, 0);
+        assert_eq!(stored, b"ab");
+    }
+
+    fn replace(replacements: &[(&str, &[u8])]) -> Changes {
+        Changes {
+            replaced: replacements.iter().map(|&(path, data)| (hash40(path), data.to_vec())).collect(),
+            ..Changes::default()
+        }
+    }
+
+    fn repack(arc: &Arc, changes: &Changes) -> io::Result<Vec<u8>> {
+        let mut out = io::Cursor::new(Vec::new());
+        arc.repack(changes, &mut out)?;
+        Ok(out.into_inner())
+    }
+
+    #[test]
+    fn repack_round_trip() {
+        let sample = fixture::sample();
+        let arc = sample.open("repack-source");
+        let model = b"new mario model ".repeat(32);
+        let data = repack(&arc, &replace(&[("fighter/mario/model.numdlb", &model)])).unwrap();
+
+        let arc = sample.open_bytes(&data, "repack");
+        let read = |path| arc.read_file(hash40(path)).unwrap();
+        // The modified directory, with its other file copied over
+        assert_eq!(read("fighter/mario/model.numdlb"), model);
+        assert_eq!(read("fighter/mario/body.nutexb"), b"mario texture");
+        // An unmodified directory and the shared section
+        assert_eq!(read("fighter/luigi/model.numdlb"), b"luigi model ".repeat(16));
+        assert_eq!(read("ui/param/ui_chara_db.prc"), b"chara db");
+        assert_eq!(read("stream:/sound/bgm/bgm_mario.nus3audio"), b"mario bgm");
+        // The shared section still starts at the last directory
+        let header = arc.tables.arc_header;
+        let last = arc.tables.folder_offsets.last().unwrap();
+        assert_eq!(header.shared_section_offset, header.file_section_offset + last.offset);
+    }
+
+    fn patch(arc: &Arc, data: Vec<u8>, changes: &Changes) -> Vec<u8> {
+        let mut out = io::Cursor::new(data);
+        arc.patch(changes, &mut out).unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn patch_in_place() {
+        let sample = fixture::sample();
+        let original = sample.build();
+        let arc = sample.open_bytes(&original, "patch-source");
+        let model = b"new mario model ".repeat(32);
+        let patched = patch(&arc, original.clone(), &replace(&[("fighter/mario/model.numdlb", &model)]));
+
+        // Only the header changes, everything else is appended
+        let header_size = size_of::<ArcHeader>();
+        assert_eq!(patched[header_size..original.len()], original[header_size..]);
+
+        let arc = sample.open_bytes(&patched, "patch");
+        let read = |path| arc.read_file(hash40(path)).unwrap();
+        assert_eq!(read("fighter/mario/model.numdlb"), model);
+        assert_eq!(read("fighter/mario/body.nutexb"), b"mario texture");
+        assert_eq!(read("fighter/luigi/model.numdlb"), b"luigi model ".repeat(16));
+        assert_eq!(read("ui/param/ui_chara_db.prc"), b"chara db");
+        assert_eq!(read("stream:/sound/bgm/bgm_mario.nus3audio"), b"mario bgm");
+    }
+
+    #[test]
+    fn repack_patched() {
+        let sample = fixture::sample();
+        let original = sample.build();
+        let arc = sample.open_bytes(&original, "patched-source");
+        let patched = patch(&arc, original, &replace(&[
+            ("stream:/sound/bgm/bgm_mario.nus3audio", b"new mario bgm"),
+            ("ui/param/ui_chara_db.prc", b"new chara db"),
+        ]));
+
+        // The patched stream is past the file section, repacking moves it back
+        let arc = sample.open_bytes(&patched, "patched");
+        let data = repack(&arc, &Changes::default()).unwrap();
+        assert!(data.len() < patched.len());
+
+        let arc = sample.open_bytes(&data, "repacked");
+        let header = arc.tables.arc_header;
+        assert!(arc.tables.stream_offset_entries
+            .iter()
+            .all(|entry| entry.offset + entry.size <= header.file_section_offset));
+        let read = |path| arc.read_file(hash40(path)).unwrap();
+        assert_eq!(read("stream:/sound/bgm/bgm_mario.nus3audio"), b"new mario bgm");
+        assert_eq!(read("ui/param/ui_chara_db.prc"), b"new chara db");
+        assert_eq!(read("fighter/mario/model.numdlb"), b"mario model ".repeat(16));
+    }
+
+    /// The sample with a regional file. Opening an arc with this only uses its labels, so the
+    /// files in `added` are labeled by adding them here
+    fn add_source(added: &[&'static str]) -> TestArc {
+        let mut arc = fixture::sample().regional_file("ui/param/ui_msg.msbt", &[b"us message", b"jp message"]);
+        for &path in added {
+            arc = arc.file(path, b"");
+        }
+        arc
+    }
+
+    fn added_changes() -> Changes {
+        Changes {
+            added: vec![
+                // Before every other directory's files and data
+                ("fighter/luigi/body.nutexb".into(), b"luigi texture".to_vec()),
+                // In the shared section, before the regional rows
+                ("ui/param/ui_stage_db.prc".into(), b"stage db ".repeat(16)),
+            ],
+            ..replace(&[("fighter/mario/body.nutexb", b"new mario texture")])
+        }
+    }
+
+    fn check_added(arc: &Arc) {
+        let read = |path| arc.read_file(hash40(path)).unwrap();
+        assert_eq!(read("fighter/luigi/body.nutexb"), b"luigi texture");
+        assert_eq!(read("ui/param/ui_stage_db.prc"), b"stage db ".repeat(16));
+        assert_eq!(read("fighter/mario/body.nutexb"), b"new mario texture");
+        assert_eq!(read("fighter/mario/model.numdlb"), b"mario model ".repeat(16));
+        assert_eq!(read("fighter/luigi/model.numdlb"), b"luigi model ".repeat(16));
+        assert_eq!(read("ui/param/ui_chara_db.prc"), b"chara db");
+        assert_eq!(read("ui/param/ui_msg.msbt"), b"us message");
+        assert_eq!(read("stream:/sound/bgm/bgm_mario.nus3audio"), b"mario bgm");
+
+        // Every path is in its bucket and every directory lists its files
+        let tables = &arc.tables;
+        for (index, path) in tables.file_info_paths.iter().enumerate() {
+            assert_eq!(arc.file_path_index(path.path.hash40()), Some(index));
+        }
+        assert_eq!(arc.get_by_path("fighter/luigi/body.nutexb"), Some(hash40("fighter/luigi/body.nutexb")));
+        assert_eq!(arc.get_by_path("ui/param/ui_stage_db.prc"), Some(hash40("ui/param/ui_stage_db.prc")));
+
+        // The regional rows still point at their own data
+        let report = arc.verify(true, None);
+        assert!(report.problems.is_empty(), "{:?}", report.problems);
+        assert_eq!(report.checked, tables.sub_files.len() + tables.stream_offset_entries.len());
+    }
+
+    #[test]
+    fn repack_adds_files() {
+        let source = add_source(&[]);
+        let arc = source.open("repack-add-source");
+        let data = repack(&arc, &added_changes()).unwrap();
+
+        let arc = add_source(&["fighter/luigi/body.nutexb", "ui/param/ui_stage_db.prc"]).open_bytes(&data, "repack-add");
+        check_added(&arc);
+        assert_eq!({ arc.tables.fs_header.file_info_count }, 8);
+    }
+
+    #[test]
+    fn patch_adds_files() {
+        let source = add_source(&[]);
+        let original = source.build();
+        let arc = source.open_bytes(&original, "patch-add-source");
+        let patched = patch(&arc, original, &added_changes());
+
+        let arc = add_source(&["fighter/luigi/body.nutexb", "ui/param/ui_stage_db.prc"]).open_bytes(&patched, "patch-add");
+        check_added(&arc);
+    }
+
+    #[test]
+    fn add_errors() {
+        let arc = fixture::sample().open("repack-add-errors");
+        let add = |path: &str| Changes { added: vec![(path.into(), b"data".to_vec())], ..Changes::default() };
+
+        // New directories aren't created
+        let err = repack(&arc, &add("fighter/peach/model.numdlb")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = repack(&arc, &add("fighter/mario/model.numdlb")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = repack(&arc, &replace(&[("fighter/peach/model.numdlb", b"peach model")])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn repack_rejects_regional_files() {
+        let arc = add_source(&[]).open("repack-regional");
+        let err = repack(&arc, &replace(&[("ui/param/ui_msg.msbt", b"message")])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("ui/param/ui_msg.msbt"));
+    }
+
+    #[test]
+    fn padding() {
+        let mut out = Vec::new();
+        assert_eq!(pad(&mut out, 0x10).unwrap(), 0x10);
+        assert_eq!(pad(&mut out, 0x11).unwrap(), 0x20);
+        assert_eq!(out.len(), 0xF);
+    }
+}
@@ -0,0 +1,112 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use zip::write::{FileOptions, ZipWriter};
+
+use crate::arc::{Arc, ArcDiff, Change};
+
+/// Where exported files are written, laid out like the arc
+pub enum Output {
+    Dir(PathBuf),
+    Zip(ZipWriter<File>),
+}
+
+impl Output {
+    /// A zip if `path` ends in `.zip`, a directory otherwise
+    pub fn create(path: &Path) -> io::Result<Self> {
+        if path.extension().is_some_and(|ext| ext == "zip") {
+            Ok(Output::Zip(ZipWriter::new(File::create(path)?)))
+        } else {
+            fs::create_dir_all(path)?;
+            Ok(Output::Dir(path.to_owned()))
+        }
+    }
+
+    pub fn add(&mut self, path: &str, data: &[u8]) -> io::Result<()> {
+        match self {
+            Output::Dir(root) => {
+                let host_path = root.join(path);
+                fs::create_dir_all(host_path.parent().unwrap())?;
+                fs::write(host_path, data)
+            }
+            Output::Zip(zip) => {
+                zip.start_file(path, FileOptions::default())?;
+                zip.write_all(data)
+            }
+        }
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        if let Output::Zip(mut zip) = self {
+            zip.finish()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Write the files and streams added or modified in `new` since the arc `diff` was made
+/// against, returning how many were written
+pub fn export_delta(new: &Arc, diff: &ArcDiff, output: &mut Output) -> io::Result<usize> {
+    let mut count = 0;
+    for change in diff.files.iter().chain(&diff.streams) {
+        if change.change != Change::Removed {
+            output.add(&change.display_path(), &new.read_change(change)?)?;
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arc::{fixture, hash40};
+    use std::io::Read;
+
+    #[test]
+    fn zip_output() {
+        let path = std::env::temp_dir().join(format!("arc-fuse-export-{}.zip", std::process::id()));
+        let mut output = Output::create(&path).unwrap();
+        output.add("fighter/mario/model.numdlb", b"model").unwrap();
+        output.finish().unwrap();
+
+        let mut zip = zip::ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let mut data = Vec::new();
+        zip.by_name("fighter/mario/model.numdlb").unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"model");
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn unlabeled_stream() {
+        let stream = "stream:/sound/bgm/bgm_secret.nus3audio";
+        let old = fixture::TestArc::new()
+            .file("fighter/mario/model.numdlb", &b"mario model ".repeat(16))
+            .file("ui/param/ui_chara_db.prc", b"chara db")
+            .stream(stream, b"old secret bgm")
+            .unlabeled(stream)
+            .open("export-old");
+        let new = fixture::TestArc::new()
+            .file("fighter/mario/model.numdlb", b"new mario model")
+            .file("ui/param/ui_chara_db.prc", b"chara db")
+            .stream(stream, b"new secret bgm")
+            .unlabeled(stream)
+            .open("export-new");
+        let diff = old.diff(&new).unwrap();
+
+        let root = std::env::temp_dir().join(format!("arc-fuse-export-{}", std::process::id()));
+        let mut output = Output::create(&root).unwrap();
+        assert_eq!(export_delta(&new, &diff, &mut output).unwrap(), 2);
+        output.finish().unwrap();
+
+        let stream_path = root.join(format!("0x{:010x}", hash40(stream)));
+        assert_eq!(fs::read(stream_path).unwrap(), b"new secret bgm");
+        assert_eq!(fs::read(root.join("fighter/mario/model.numdlb")).unwrap(), b"new mario model");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
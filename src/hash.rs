@@ -0,0 +1,227 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crc::crc32::{update, IEEE_TABLE};
+use rayon::prelude::*;
+
+/// Placeholder for wordlists given without a name
+const DEFAULT_WORDLIST: &str = "";
+
+/// Parse a hash40 written in hex, with or without a leading 0x
+pub fn parse_hash40(hash: &str) -> io::Result<u64> {
+    let digits = hash.trim_start_matches("0x").trim_start_matches("0X");
+    u64::from_str_radix(digits, 16)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid hash40 {}", hash)))
+}
+
+/// Non-empty lines of a text file
+pub fn read_lines<P: AsRef<Path>>(path: P) -> io::Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// Write every string in `strings` with its hash40, in the format of `hash40s.tsv`
+pub fn write_labels<W: io::Write>(mut writer: W, strings: &[String]) -> io::Result<()> {
+    for string in strings {
+        writeln!(writer, "{:X}\t{}", crate::arc::hash40(string), string)?;
+    }
+
+    Ok(())
+}
+
+enum Segment {
+    Literal(String),
+    Choice(Vec<String>),
+}
+
+impl Segment {
+    fn options(&self) -> &[String] {
+        match self {
+            Segment::Literal(literal) => std::slice::from_ref(literal),
+            Segment::Choice(options) => options,
+        }
+    }
+}
+
+/// A path with placeholders, expanding to every combination of their values:
+/// `{a..b}` for numbers (zero padded to the width of `a`), `{x,y}` for alternatives and
+/// `{name}` for the words of a wordlist
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+fn invalid_template(template: &str, msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", template, msg))
+}
+
+impl Template {
+    pub fn parse(template: &str, wordlists: &HashMap<String, Vec<String>>) -> io::Result<Self> {
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..].find('}').ok_or_else(|| invalid_template(template, "unclosed {"))? + start;
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_owned()));
+            }
+            segments.push(Segment::Choice(Template::expand(&rest[start + 1..end], wordlists)
+                .map_err(|msg| invalid_template(template, &msg))?));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_owned()));
+        }
+
+        Ok(Template { segments })
+    }
+
+    fn expand(placeholder: &str, wordlists: &HashMap<String, Vec<String>>) -> Result<Vec<String>, String> {
+        if let Some((first, last)) = placeholder.split_once("..") {
+            let (start, end) = match (first.parse::<u64>(), last.parse::<u64>()) {
+                (Ok(start), Ok(end)) if start <= end => (start, end),
+                _ => return Err(format!("invalid range {{{}}}", placeholder)),
+            };
+            let width = first.len();
+            Ok((start..=end).map(|n| format!("{:0width$}", n, width = width)).collect())
+        } else if placeholder.contains(',') {
+            Ok(placeholder.split(',').map(String::from).collect())
+        } else {
+            wordlists
+                .get(placeholder)
+                .or_else(|| wordlists.get(DEFAULT_WORDLIST))
+                .cloned()
+                .ok_or_else(|| format!("no wordlist for {{{}}}", placeholder))
+        }
+    }
+
+    /// Number of strings the template expands to
+    pub fn count(&self) -> u128 {
+        self.segments.iter().map(|segment| segment.options().len() as u128).product()
+    }
+
+    /// Every expansion of the template whose hash40 is in `targets`. Expansions are only
+    /// built as far as they can still reach the length of one of the targets
+    pub fn search(&self, targets: &HashSet<u64>) -> Vec<(u64, String)> {
+        let mut target_lengths = [false; 256];
+        for hash40 in targets {
+            target_lengths[(hash40 >> 32) as u8 as usize] = true;
+        }
+
+        // Lengths the segments from each index on can add up to
+        let mut suffix_lengths = vec![[false; 256]; self.segments.len() + 1];
+        suffix_lengths[self.segments.len()][0] = true;
+        for i in (0..self.segments.len()).rev() {
+            for option in self.segments[i].options() {
+                for length in 0..256 - option.len().min(256) {
+                    if suffix_lengths[i + 1][length] {
+                        suffix_lengths[i][length + option.len()] = true;
+                    }
+                }
+            }
+        }
+
+        let search = Search { template: self, targets, target_lengths, suffix_lengths };
+        let first = self.segments.iter().position(|segment| segment.options().len() > 1);
+        match first {
+            // Split the work on the first placeholder with more than one value
+            Some(first) => {
+                let mut prefix = String::new();
+                for segment in &self.segments[..first] {
+                    prefix.push_str(&segment.options()[0]);
+                }
+                self.segments[first]
+                    .options()
+                    .par_iter()
+                    .flat_map(|option| {
+                        let mut path = prefix.clone();
+                        path.push_str(option);
+                        let mut found = Vec::new();
+                        let crc = update(0, &IEEE_TABLE, path.as_bytes());
+                        search.expand(first + 1, crc, &mut path, &mut found);
+                        found
+                    })
+                    .collect()
+            }
+            None => {
+                let mut found = Vec::new();
+                search.expand(0, 0, &mut String::new(), &mut found);
+                found
+            }
+        }
+    }
+}
+
+struct Search<'a> {
+    template: &'a Template,
+    targets: &'a HashSet<u64>,
+    target_lengths: [bool; 256],
+    suffix_lengths: Vec<[bool; 256]>,
+}
+
+impl Search<'_> {
+    /// Whether the segments from `index` on can still complete `path` to a target length
+    fn reachable(&self, index: usize, length: usize) -> bool {
+        (length..256).any(|target| self.target_lengths[target] && self.suffix_lengths[index][target - length])
+    }
+
+    fn expand(&self, index: usize, crc: u32, path: &mut String, found: &mut Vec<(u64, String)>) {
+        if !self.reachable(index, path.len()) {
+            return
+        }
+        if index == self.template.segments.len() {
+            let hash40 = crc as u64 + ((path.len() as u64) << 32);
+            if self.targets.contains(&hash40) {
+                found.push((hash40, path.clone()));
+            }
+            return
+        }
+
+        let len = path.len();
+        for option in self.template.segments[index].options() {
+            path.push_str(option);
+            self.expand(index + 1, update(crc, &IEEE_TABLE, option.as_bytes()), path, found);
+            path.truncate(len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arc::hash40;
+
+    #[test]
+    fn expand_template() {
+        let mut wordlists = HashMap::new();
+        wordlists.insert(String::from("name"), vec![String::from("mario"), String::from("luigi")]);
+
+        let template = Template::parse("fighter/{name}/model/body/c{00..07}/{model,body}.numdlb", &wordlists).unwrap();
+        assert_eq!(template.count(), 2 * 8 * 2);
+
+        let targets = [
+            hash40("fighter/luigi/model/body/c05/model.numdlb"),
+            hash40("fighter/mario/model/body/c00/body.numdlb"),
+            hash40("fighter/peach/model/body/c00/body.numdlb"),
+        ].iter().cloned().collect();
+        let mut found: Vec<_> = template.search(&targets).into_iter().map(|(_, path)| path).collect();
+        found.sort();
+        assert_eq!(found, vec![
+            "fighter/luigi/model/body/c05/model.numdlb",
+            "fighter/mario/model/body/c00/body.numdlb",
+        ]);
+    }
+
+    #[test]
+    fn invalid_templates() {
+        let wordlists = HashMap::new();
+        assert!(Template::parse("fighter/{name", &wordlists).is_err());
+        assert!(Template::parse("fighter/{name}", &wordlists).is_err());
+        assert!(Template::parse("c{07..00}", &wordlists).is_err());
+        assert_eq!(parse_hash40("0x0a1b2c3d4e").unwrap(), 0x0a1b2c3d4e);
+    }
+}
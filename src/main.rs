@@ -2,23 +2,44 @@ extern crate fuse;
 extern crate zstd;
 extern crate memmap;
 extern crate env_logger;
+#[macro_use] extern crate log;
 extern crate packed_struct;
 #[macro_use] extern crate packed_struct_codegen;
 
-use std::env;
+use std::collections::HashMap;
 use std::ffi::OsStr;
-use std::path::Path;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
 use time::Timespec;
-use libc::ENOENT;
-use fuse::{FileType, FileAttr, Filesystem, Request, ReplyData, ReplyEntry, ReplyAttr, ReplyDirectory};
+use libc::{ENOENT, EROFS, EIO, ENOTEMPTY, ENODATA, ERANGE, EINVAL, ENOSYS};
+use fuse::{
+    FileType, FileAttr, Filesystem, Request, ReplyData, ReplyEntry, ReplyAttr, ReplyDirectory,
+    ReplyWrite, ReplyCreate, ReplyEmpty, ReplyXattr
+};
 
 mod arc;
+mod overlay;
+mod mods;
+mod export;
+mod hash;
+mod virtual_fs;
+mod trace;
+
+use overlay::Overlay;
+use mods::Mods;
+use trace::OpTrace;
 
 const TTL: Timespec = Timespec {
     sec: 1,
     nsec: 0,
 };
 
+/// Extended attribute holding the layer (arc, mod directory or overlay) a file comes from
+const LAYER_XATTR: &str = "user.arc.layer";
+
 const UNIX_EPOCH: Timespec = Timespec {
     sec: 0,
     nsec: 0,
@@ -27,249 +48,1172 @@ const UNIX_EPOCH: Timespec = Timespec {
 
 struct ArcFS {
     pub arc: arc::Arc,
+    pub overlay: Option<Overlay>,
+    pub mods: Option<Mods>,
+    /// Arc path of every inode the kernel has looked up, used to find overlay files
+    paths: HashMap<u64, String>,
+    /// Content of the generated files in the virtual directories, by inode
+    virtual_files: HashMap<u64, Vec<u8>>,
+    /// Built the first time `.by-ext` is accessed
+    extensions: Option<virtual_fs::ExtensionIndex>,
+    /// Arc file or directory of every inode in `.raw`
+    raw: HashMap<u64, u64>,
 }
 
 impl ArcFS {
     pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
-        Ok(Self { arc: arc::Arc::open(path)? })
+        Ok(Self::new(arc::Arc::open(path)?))
+    }
+
+    pub fn open_lazy<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Ok(Self::new(arc::Arc::open_lazy(path)?))
+    }
+
+    pub fn open_cached<P: AsRef<Path>, C: AsRef<Path>>(path: P, cache_dir: C) -> std::io::Result<Self> {
+        Ok(Self::new(arc::Arc::open_cached(path, cache_dir)?))
+    }
+
+    fn new(arc: arc::Arc) -> Self {
+        let mut paths = HashMap::new();
+        paths.insert(0, String::new());
+        Self { arc, overlay: None, mods: None, paths, virtual_files: HashMap::new(), extensions: None, raw: HashMap::new() }
+    }
+
+    /// Send all changes to the mount to the host directory `overlay` instead of failing
+    pub fn with_overlay(mut self, overlay: Overlay) -> Self {
+        self.overlay = Some(overlay);
+        self
+    }
+
+    /// Merge the read-only mod directories of `mods` over the arc
+    pub fn with_mods(mut self, mods: Mods) -> Self {
+        self.mods = Some(mods);
+        self
+    }
+
+    fn child_path(&self, parent: u64, name: &OsStr) -> Option<String> {
+        let parent_path = self.paths.get(&parent)?;
+        let name = name.to_str()?;
+        Some(if parent_path.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{}/{}", parent_path, name)
+        })
+    }
+
+    /// Inode for the child `name` of `parent`, files only in the overlay use the hash40 of
+    /// their path like files in the arc
+    fn child_ino(&self, parent: u64, name: &OsStr, path: &str) -> u64 {
+        name.to_str()
+            .and_then(|name| self.arc.get_child(parent, name))
+            .unwrap_or_else(|| arc::hash40(path))
+    }
+
+    fn overlay_file(&self, ino: u64) -> Option<PathBuf> {
+        self.overlay.as_ref()?.get(self.paths.get(&ino)?)
+    }
+
+    fn mod_file(&self, ino: u64) -> Option<PathBuf> {
+        self.mods.as_ref()?.get(self.paths.get(&ino)?)
+    }
+
+    /// Get the file on the host replacing `ino`, the overlay takes precedence over the mods
+    fn host_file(&self, ino: u64) -> Option<PathBuf> {
+        self.overlay_file(ino).or_else(|| self.mod_file(ino))
+    }
+
+    fn in_mods(&self, path: &str) -> bool {
+        self.mods.as_ref().map(|mods| mods.get(path).is_some()).unwrap_or(false)
+    }
+
+    /// Name of the layer `ino` is read from
+    fn layer(&self, ino: u64) -> Option<String> {
+        let path = self.paths.get(&ino)?;
+        if let Some(overlay) = self.overlay.as_ref().filter(|overlay| overlay.get(path).is_some()) {
+            Some(overlay.root().display().to_string())
+        } else if let Some(layer) = self.mods.as_ref().and_then(|mods| mods.layer(path)) {
+            Some(layer.display().to_string())
+        } else if self.arc.files.contains_key(&ino) {
+            Some(String::from("arc"))
+        } else {
+            None
+        }
+    }
+
+    fn is_deleted(&self, path: &str) -> bool {
+        self.overlay.as_ref().map(|overlay| overlay.is_deleted(path)).unwrap_or(false)
+    }
+
+    fn attr(&self, req: &Request, ino: u64) -> Option<FileAttr> {
+        if let Some(host_path) = self.host_file(ino) {
+            let metadata = fs::metadata(host_path).ok()?;
+            let kind = if metadata.is_dir() { FileType::Directory } else { FileType::RegularFile };
+            let mut attr = file_attr(req, ino, kind, metadata.len());
+            attr.mtime = Timespec::new(metadata.mtime(), metadata.mtime_nsec() as i32);
+            attr.ctime = Timespec::new(metadata.ctime(), metadata.ctime_nsec() as i32);
+            attr.atime = Timespec::new(metadata.atime(), metadata.atime_nsec() as i32);
+            return Some(attr)
+        }
+
+        match self.arc.files.get(&ino) {
+            Some(arc::ArcFileInfo::Directory) => {
+                Some(file_attr(req, ino, FileType::Directory, 0))
+            }
+            Some(arc::ArcFileInfo::Uncompressed { data, .. }) => {
+                Some(file_attr(req, ino, FileType::RegularFile, data.len() as u64))
+            }
+            Some(arc::ArcFileInfo::Compressed { decomp_size, .. }) => {
+                Some(file_attr(req, ino, FileType::RegularFile, *decomp_size))
+            }
+            None => {
+                None
+            }
+            _ => {
+                warn!(target: "fuse", "0x{:010x} is neither a file nor a directory", ino);
+                None
+            }
+        }
+    }
+
+    /// Get the overlay copy of `ino`, copying the file out of the mods or the arc if it
+    /// hasn't been modified yet
+    fn copy_up(&self, ino: u64) -> io::Result<PathBuf> {
+        let overlay = self.overlay.as_ref()
+            .ok_or_else(|| io::Error::from_raw_os_error(EROFS))?;
+        if let Some(host_path) = self.overlay_file(ino) {
+            return Ok(host_path)
+        }
+
+        let path = self.paths.get(&ino)
+            .ok_or_else(|| io::Error::from_raw_os_error(ENOENT))?;
+        if virtual_fs::is_virtual(path) {
+            return Err(io::Error::from_raw_os_error(EROFS))
+        }
+        if let Some(mod_path) = self.mod_file(ino) {
+            return overlay.create_file(path, &fs::read(mod_path)?)
+        }
+        let data = self.arc.get_file_data(ino)?;
+        overlay.create_file(path, data.get_slice())
+    }
+}
+
+fn file_attr(req: &Request, ino: u64, kind: FileType, size: u64) -> FileAttr {
+    FileAttr {
+        ino,
+        size,
+        blocks: if kind == FileType::Directory { 0 } else { 1 },
+        atime: UNIX_EPOCH,
+        mtime: UNIX_EPOCH,
+        ctime: UNIX_EPOCH,
+        crtime: UNIX_EPOCH,
+        kind,
+        perm: if kind == FileType::Directory { 0o755 } else { 0o644 },
+        nlink: if kind == FileType::Directory { 2 } else { 1 },
+        uid: req.uid(),
+        gid: req.gid(),
+        rdev: 0,
+        flags: 0,
+    }
+}
+
+fn io_error_code(err: io::Error) -> i32 {
+    match err.raw_os_error() {
+        Some(code) => code,
+        None if err.kind() == io::ErrorKind::NotFound => ENOENT,
+        None => EIO,
     }
 }
 
 impl Filesystem for ArcFS {
     fn init(&mut self, _req: &Request) -> Result<(), i32> {
-        println!("Arc successfully mounted");
-        #[cfg(feature="print")]
-        {
-            let inode: &u64 = &env::args_os().nth(3).unwrap().to_str().unwrap().parse().unwrap();
-            let name = self.arc.names.get(inode);
-            let stream_path = self.arc.stream_paths.get(inode);
-            let dir_children = self.arc.dir_children.get(inode);
-            let file = self.arc.files.get(inode);
-            let stem = self.arc.stems.get(inode);
-            dbg!(name, stream_path, stem, file, dir_children);
-            std::process::exit(0);
-        }
+        info!(target: "fuse", "Arc successfully mounted");
         Ok(())
     }
 
     fn lookup(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let _trace = OpTrace::start(|| format!("lookup(0x{:010x}, {:?})", parent, name));
+        if overlay::is_reserved(name) {
+            return reply.error(ENOENT)
+        }
         let parent = if parent == 1 { 0 } else { parent };
-        if let Some(a) = self.arc.get_name(parent) {
-            let file_path = String::from(a) +
-                if a.len() != 0 { "/" } else { "" } +
-                name.to_str().unwrap();
-            
-            let hash40 = arc::hash40(&file_path);
-            match self.arc.files.get(&hash40) {
-                Some(arc::ArcFileInfo::Directory) => {
-                    reply.entry(&TTL, &FileAttr {
-                            ino: hash40,
-                            size: 0,
-                            blocks: 0,
-                            atime: UNIX_EPOCH,
-                            mtime: UNIX_EPOCH,
-                            ctime: UNIX_EPOCH,
-                            crtime: UNIX_EPOCH,
-                            kind: FileType::Directory,
-                            perm: 0o755,
-                            nlink: 2,
-                            uid: req.uid(),
-                            gid: req.gid(),
-                            rdev: 0,
-                            flags: 0, 
-                    }, 0);
-                }
-                Some(arc::ArcFileInfo::Uncompressed { data, .. }) => {
-                    reply.entry(&TTL, &FileAttr {
-                        ino: hash40,
-                        size: data.len() as u64,
-                        blocks: 1,
-                        atime: UNIX_EPOCH,
-                        mtime: UNIX_EPOCH,
-                        ctime: UNIX_EPOCH,
-                        crtime: UNIX_EPOCH,
-                        kind: FileType::RegularFile,
-                        perm: 0o644,
-                        nlink: 1,
-                        uid: req.uid(),
-                        gid: req.gid(),
-                        rdev: 0,
-                        flags: 0, 
-                    }, 0);
-                }
-                Some(arc::ArcFileInfo::Compressed { decomp_size, .. }) => {
-                    reply.entry(&TTL, &FileAttr {
-                        ino: hash40,
-                        size: *decomp_size,
-                        blocks: 1,
-                        atime: UNIX_EPOCH,
-                        mtime: UNIX_EPOCH,
-                        ctime: UNIX_EPOCH,
-                        crtime: UNIX_EPOCH,
-                        kind: FileType::RegularFile,
-                        perm: 0o644,
-                        nlink: 1,
-                        uid: req.uid(),
-                        gid: req.gid(),
-                        rdev: 0,
-                        flags: 0, 
-                    }, 0);
-                }
-                None => {
-                    //dbg!("File does not exist", hash40);
-                    //dbg!(a, file_path, name);
-                    reply.error(ENOENT);
-                }
-                _ => {
-                    dbg!("Error: filetype match fail");
-                    reply.error(ENOENT);
+        self.arc.load_dir(parent);
+        let path = match self.child_path(parent, name) {
+            Some(path) if !self.is_deleted(&path) => path,
+            _ => {
+                reply.error(ENOENT);
+                return
+            }
+        };
+        if virtual_fs::is_virtual(&path) {
+            match self.lookup_virtual(req, path) {
+                Some(attr) => reply.entry(&TTL, &attr, 0),
+                None => reply.error(ENOENT),
+            }
+            return
+        }
+
+        let ino = self.child_ino(parent, name, &path);
+        let old_path = self.paths.insert(ino, path);
+        match self.attr(req, ino) {
+            Some(attr) => {
+                reply.entry(&TTL, &attr, 0);
+            }
+            None => {
+                debug!(target: "fuse", "lookup: 0x{:010x} does not exist", ino);
+                if old_path.is_none() {
+                    self.paths.remove(&ino);
                 }
+                reply.error(ENOENT);
             }
-        } else {
-            dbg!("Error: name not found");
-            reply.error(ENOENT);
         }
     }
 
     fn getattr(&mut self, req: &Request, ino: u64, reply: ReplyAttr) {
+        let _trace = OpTrace::start(|| format!("getattr(0x{:010x})", ino));
         let ino = if ino == 1 { 0 } else { ino };
-        match self.arc.files.get(&ino) {
-            Some(arc::ArcFileInfo::Directory) => {
-                reply.attr(&TTL, &FileAttr {
-                        ino,
-                        size: 0,
-                        blocks: 0,
-                        atime: UNIX_EPOCH,
-                        mtime: UNIX_EPOCH,
-                        ctime: UNIX_EPOCH,
-                        crtime: UNIX_EPOCH,
-                        kind: FileType::Directory,
-                        perm: 0o755,
-                        nlink: 2,
-                        uid: req.uid(),
-                        gid: req.gid(),
-                        rdev: 0,
-                        flags: 0, 
-                });
-            }
-            Some(arc::ArcFileInfo::Uncompressed { data, .. }) => {
-                reply.attr(&TTL, &FileAttr {
-                    ino,
-                    size: data.len() as u64,
-                    blocks: 1,
-                    atime: UNIX_EPOCH,
-                    mtime: UNIX_EPOCH,
-                    ctime: UNIX_EPOCH,
-                    crtime: UNIX_EPOCH,
-                    kind: FileType::RegularFile,
-                    perm: 0o644,
-                    nlink: 1,
-                    uid: req.uid(),
-                    gid: req.gid(),
-                    rdev: 0,
-                    flags: 0, 
-                });
-            }
-            Some(arc::ArcFileInfo::Compressed { decomp_size, .. }) => {
-                reply.attr(&TTL, &FileAttr {
-                    ino,
-                    size: *decomp_size,
-                    blocks: 1,
-                    atime: UNIX_EPOCH,
-                    mtime: UNIX_EPOCH,
-                    ctime: UNIX_EPOCH,
-                    crtime: UNIX_EPOCH,
-                    kind: FileType::RegularFile,
-                    perm: 0o644,
-                    nlink: 1,
-                    uid: req.uid(),
-                    gid: req.gid(),
-                    rdev: 0,
-                    flags: 0, 
-                });
+        match self.virtual_attr(req, ino).or_else(|| self.attr(req, ino)) {
+            Some(attr) => {
+                reply.attr(&TTL, &attr);
             }
             None => {
-                dbg!("File does not exist");
+                debug!(target: "fuse", "getattr: 0x{:010x} does not exist", ino);
                 reply.error(ENOENT);
             }
-            _ => {
-                dbg!("Error: filetype match fail");
-                reply.error(ENOENT);
+        }
+    }
+
+    fn setattr(
+        &mut self, req: &Request, ino: u64, _mode: Option<u32>, _uid: Option<u32>,
+        _gid: Option<u32>, size: Option<u64>, _atime: Option<Timespec>,
+        _mtime: Option<Timespec>, _fh: Option<u64>, _crtime: Option<Timespec>,
+        _chgtime: Option<Timespec>, _bkuptime: Option<Timespec>, _flags: Option<u32>,
+        reply: ReplyAttr
+    ) {
+        let _trace = OpTrace::start(|| format!("setattr(0x{:010x}, size: {:?})", ino, size));
+        let ino = if ino == 1 { 0 } else { ino };
+
+        // Only truncating is supported, everything else is reported back unchanged
+        if let Some(size) = size {
+            let truncated = self.copy_up(ino)
+                .and_then(|host_path| fs::OpenOptions::new().write(true).open(host_path))
+                .and_then(|file| file.set_len(size));
+            if let Err(err) = truncated {
+                reply.error(io_error_code(err));
+                return
             }
         }
+
+        match self.attr(req, ino) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(ENOENT),
+        }
     }
 
     fn read(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, size: u32, reply: ReplyData) {
-        if let Some(data) = self.arc.get_file_data(ino) {
-            let data = data.get_slice();
-            let start = offset as usize;
+        let _trace = OpTrace::start(|| format!("read(0x{:010x}, offset: {}, size: {})", ino, offset, size));
+        if let Some(data) = self.virtual_data(ino) {
+            let start = usize::min(offset as usize, data.len());
             let end = usize::min((offset as usize) + (size as usize), data.len());
             reply.data(&data[start..end]);
+        } else if let Some(host_path) = self.host_file(ino) {
+            let mut data = vec![0; size as usize];
+            let read = fs::File::open(host_path)
+                .and_then(|file| file.read_at(&mut data, offset as u64));
+            match read {
+                Ok(len) => reply.data(&data[..len]),
+                Err(err) => reply.error(io_error_code(err)),
+            }
         } else {
-            dbg!("Failed to get data");
-            reply.error(ENOENT);
+            match self.arc.get_file_data(ino) {
+                Ok(data) => {
+                    let data = data.get_slice();
+                    let start = usize::min(offset as usize, data.len());
+                    let end = usize::min((offset as usize) + (size as usize), data.len());
+                    reply.data(&data[start..end]);
+                }
+                Err(err) => reply.error(io_error_code(err)),
+            }
+        }
+    }
+
+    fn write(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, data: &[u8], _flags: u32, reply: ReplyWrite) {
+        let _trace = OpTrace::start(|| format!("write(0x{:010x}, offset: {}, size: {})", ino, offset, data.len()));
+        let written = self.copy_up(ino)
+            .and_then(|host_path| fs::OpenOptions::new().write(true).open(host_path))
+            .and_then(|file| file.write_all_at(data, offset as u64));
+        match written {
+            Ok(()) => reply.written(data.len() as u32),
+            Err(err) => reply.error(io_error_code(err)),
+        }
+    }
+
+    fn create(&mut self, req: &Request, parent: u64, name: &OsStr, _mode: u32, flags: u32, reply: ReplyCreate) {
+        let _trace = OpTrace::start(|| format!("create(0x{:010x}, {:?})", parent, name));
+        // A whiteout marker created through the mount would hide the file it names
+        if overlay::is_reserved(name) {
+            return reply.error(EINVAL)
+        }
+        let parent = if parent == 1 { 0 } else { parent };
+        let (overlay, path) = match (&self.overlay, self.child_path(parent, name)) {
+            (Some(_), Some(path)) if virtual_fs::is_virtual(&path) => return reply.error(EROFS),
+            (Some(overlay), Some(path)) => (overlay, path),
+            (None, _) => return reply.error(EROFS),
+            (_, None) => return reply.error(ENOENT),
+        };
+
+        if let Err(err) = overlay.create_file(&path, &[]) {
+            return reply.error(io_error_code(err))
+        }
+
+        let ino = self.child_ino(parent, name, &path);
+        self.paths.insert(ino, path);
+        match self.attr(req, ino) {
+            Some(attr) => reply.created(&TTL, &attr, 0, 0, flags),
+            None => reply.error(EIO),
+        }
+    }
+
+    fn mkdir(&mut self, req: &Request, parent: u64, name: &OsStr, _mode: u32, reply: ReplyEntry) {
+        let _trace = OpTrace::start(|| format!("mkdir(0x{:010x}, {:?})", parent, name));
+        // A whiteout marker created through the mount would hide the file it names
+        if overlay::is_reserved(name) {
+            return reply.error(EINVAL)
+        }
+        let parent = if parent == 1 { 0 } else { parent };
+        let (overlay, path) = match (&self.overlay, self.child_path(parent, name)) {
+            (Some(_), Some(path)) if virtual_fs::is_virtual(&path) => return reply.error(EROFS),
+            (Some(overlay), Some(path)) => (overlay, path),
+            (None, _) => return reply.error(EROFS),
+            (_, None) => return reply.error(ENOENT),
+        };
+
+        if let Err(err) = overlay.create_dir(&path) {
+            return reply.error(io_error_code(err))
+        }
+
+        let ino = self.child_ino(parent, name, &path);
+        self.paths.insert(ino, path);
+        match self.attr(req, ino) {
+            Some(attr) => reply.entry(&TTL, &attr, 0),
+            None => reply.error(EIO),
+        }
+    }
+
+    fn unlink(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let _trace = OpTrace::start(|| format!("unlink(0x{:010x}, {:?})", parent, name));
+        let parent = if parent == 1 { 0 } else { parent };
+        let (overlay, path) = match (&self.overlay, self.child_path(parent, name)) {
+            (Some(_), Some(path)) if virtual_fs::is_virtual(&path) => return reply.error(EROFS),
+            (Some(overlay), Some(path)) => (overlay, path),
+            (None, _) => return reply.error(EROFS),
+            (_, None) => return reply.error(ENOENT),
+        };
+
+        let in_overlay = overlay.get(&path).is_some();
+        // Files from the mods can't be removed, so they're hidden the same way as arc files
+        let in_arc = name.to_str().and_then(|name| self.arc.get_child(parent, name)).is_some() ||
+            self.in_mods(&path);
+        let result = if in_overlay { fs::remove_file(overlay.path(&path)) } else { Ok(()) }
+            .and_then(|_| if in_arc { overlay.delete(&path) } else { Ok(()) });
+
+        match result {
+            Ok(()) if in_overlay || in_arc => reply.ok(),
+            Ok(()) => reply.error(ENOENT),
+            Err(err) => reply.error(io_error_code(err)),
+        }
+    }
+
+    fn rename(&mut self, _req: &Request, parent: u64, name: &OsStr, newparent: u64, newname: &OsStr, reply: ReplyEmpty) {
+        let _trace = OpTrace::start(|| {
+            format!("rename(0x{:010x}, {:?}, 0x{:010x}, {:?})", parent, name, newparent, newname)
+        });
+        if overlay::is_reserved(name) || overlay::is_reserved(newname) {
+            return reply.error(EINVAL)
+        }
+        // Moving files between layers isn't supported
+        reply.error(ENOSYS)
+    }
+
+    fn rmdir(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let _trace = OpTrace::start(|| format!("rmdir(0x{:010x}, {:?})", parent, name));
+        let parent = if parent == 1 { 0 } else { parent };
+        let path = match (&self.overlay, self.child_path(parent, name)) {
+            (Some(_), Some(path)) if virtual_fs::is_virtual(&path) => return reply.error(EROFS),
+            (Some(_), Some(path)) => path,
+            (None, _) => return reply.error(EROFS),
+            (_, None) => return reply.error(ENOENT),
+        };
+
+        let arc_dir = name.to_str().and_then(|name| self.arc.get_child(parent, name));
+        let mod_children = self.mods.as_ref().map(|mods| mods.read_dir(&path)).unwrap_or(&[]);
+        let has_mod_children = mod_children.iter().any(|(child, _)| {
+            !self.is_deleted(&format!("{}/{}", path, child))
+        });
+        if has_mod_children {
+            return reply.error(ENOTEMPTY)
+        }
+        if let Some(dir) = arc_dir {
+            self.arc.load_dir(dir);
+            let has_children = self.arc.dir_children
+                .get(&dir)
+                .map(|children| {
+                    children.iter().any(|child| {
+                        let child_path = format!("{}/{}", path, self.arc.stems[child]);
+                        !self.overlay.as_ref().unwrap().is_deleted(&child_path)
+                    })
+                })
+                .unwrap_or(false);
+            if has_children {
+                return reply.error(ENOTEMPTY)
+            }
+        }
+
+        let overlay = self.overlay.as_ref().unwrap();
+        let in_overlay = overlay.get(&path).is_some();
+        let in_lower = arc_dir.is_some() || self.in_mods(&path);
+        let result = if in_overlay { fs::remove_dir(overlay.path(&path)) } else { Ok(()) }
+            .and_then(|_| if in_lower { overlay.delete(&path) } else { Ok(()) });
+
+        match result {
+            Ok(()) if in_overlay || in_lower => reply.ok(),
+            Ok(()) => reply.error(ENOENT),
+            Err(err) => reply.error(io_error_code(err)),
         }
     }
 
     fn readdir(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        let _trace = OpTrace::start(|| format!("readdir(0x{:010x}, offset: {})", ino, offset));
         let ino = if ino == 1 { 0 } else { ino };
-        if let Some(children) = self.arc.dir_children.get(&ino) {
-            let mut entries = vec![
-                (1, FileType::Directory, "."),
-                (1, FileType::Directory, ".."),
-            ];
-            for child in children {
-                if *child == 0 {
-                    continue;
-                }
-                entries.push(
-                    (
-                        *child,
-                        match self.arc.files.get(&child) {
-                            Some(arc::ArcFileInfo::Directory) => {
-                                FileType::Directory
-                            }
-                            Some(arc::ArcFileInfo::Uncompressed { ..  }) |
-                            Some(arc::ArcFileInfo::Compressed { .. }) => {
-                                FileType::RegularFile
-                            }
-                            _ => {
-                                panic!("Improper type")
-                            }
-                        },
-                        self.arc.stems.get(&child).unwrap()
-                    )
-                )
-            }
-            
+        if let Some(children) = self.virtual_path(ino).and_then(|path| self.virtual_children(path)) {
+            let entries = [(ino, FileType::Directory, String::from(".")), (1, FileType::Directory, String::from(".."))];
             let to_skip = if offset == 0 { offset } else { offset + 1 } as usize;
-            for (i, entry) in entries.into_iter().enumerate().skip(to_skip) {
+            for (i, entry) in entries.iter().cloned().chain(children).enumerate().skip(to_skip) {
                 reply.add(entry.0, i as i64, entry.1, entry.2);
             }
             reply.ok();
-        } else {
-            println!("Not found");
+            return
+        }
+        self.arc.load_dir(ino);
+        let path = self.paths.get(&ino).cloned().unwrap_or_default();
+        let mut host_children = match &self.overlay {
+            Some(overlay) if !self.is_deleted(&path) => overlay.read_dir(&path),
+            _ => Vec::new(),
+        };
+        if let Some(mods) = &self.mods {
+            host_children.extend(
+                mods.read_dir(&path)
+                    .iter()
+                    .filter(|(name, _)| {
+                        let child_path = if path.is_empty() { name.clone() } else { format!("{}/{}", path, name) };
+                        !self.is_deleted(&child_path)
+                    })
+                    .cloned()
+            );
+        }
+
+        let arc_children = self.arc.dir_children.get(&ino);
+        if arc_children.is_none() && self.host_file(ino).is_none() {
+            debug!(target: "fuse", "readdir: 0x{:010x} is not a directory", ino);
             reply.error(ENOENT);
+            return
+        }
+
+        let mut entries = vec![
+            (1, FileType::Directory, String::from(".")),
+            (1, FileType::Directory, String::from("..")),
+        ];
+        for child in arc_children.into_iter().flatten() {
+            if *child == 0 {
+                continue;
+            }
+            let stem = self.arc.stems.get(child).unwrap();
+            let child_path = if path.is_empty() { stem.to_string() } else { format!("{}/{}", path, stem) };
+            if self.is_deleted(&child_path) {
+                continue;
+            }
+            entries.push(
+                (
+                    *child,
+                    match self.arc.files.get(child) {
+                        Some(arc::ArcFileInfo::Directory) => {
+                            FileType::Directory
+                        }
+                        Some(arc::ArcFileInfo::Uncompressed { ..  }) |
+                        Some(arc::ArcFileInfo::Compressed { .. }) => {
+                            FileType::RegularFile
+                        }
+                        _ => {
+                            panic!("Improper type")
+                        }
+                    },
+                    stem.to_string()
+                )
+            )
+        }
+
+        if path.is_empty() {
+            entries.extend(self.virtual_children(&path).into_iter().flatten());
+        }
+        for (name, is_dir) in host_children {
+            if entries.iter().any(|entry| entry.2 == name) {
+                continue;
+            }
+            let child_path = if path.is_empty() { name.clone() } else { format!("{}/{}", path, name) };
+            let kind = if is_dir { FileType::Directory } else { FileType::RegularFile };
+            entries.push((arc::hash40(&child_path), kind, name));
+        }
+
+        let to_skip = if offset == 0 { offset } else { offset + 1 } as usize;
+        for (i, entry) in entries.into_iter().enumerate().skip(to_skip) {
+            reply.add(entry.0, i as i64, entry.1, entry.2);
+        }
+        reply.ok();
+    }
+
+    fn getxattr(&mut self, _req: &Request, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        let _trace = OpTrace::start(|| format!("getxattr(0x{:010x}, {:?})", ino, name));
+        let ino = if ino == 1 { 0 } else { ino };
+        let layer = match self.layer(ino) {
+            Some(layer) if name == LAYER_XATTR => layer,
+            _ => return reply.error(ENODATA),
+        };
+
+        if size == 0 {
+            reply.size(layer.len() as u32);
+        } else if size < layer.len() as u32 {
+            reply.error(ERANGE);
+        } else {
+            reply.data(layer.as_bytes());
         }
     }
+
+    fn listxattr(&mut self, _req: &Request, ino: u64, size: u32, reply: ReplyXattr) {
+        let _trace = OpTrace::start(|| format!("listxattr(0x{:010x})", ino));
+        let names = [LAYER_XATTR.as_bytes(), b"\0"].concat();
+        if size == 0 {
+            reply.size(names.len() as u32);
+        } else if size < names.len() as u32 {
+            reply.error(ERANGE);
+        } else {
+            reply.data(&names);
+        }
+    }
+}
+
+#[derive(StructOpt)]
+#[structopt(about = "A fuse wrapper for Smash Ultimate's ARC filetype")]
+enum Args {
+    /// Mount an arc as a filesystem
+    Mount(MountArgs),
+
+    /// Write a new arc with files replaced by the ones in a directory laid out like the arc
+    Repack {
+        /// data.arc to start from
+        #[structopt(parse(from_os_str))]
+        arc: PathBuf,
+
+        /// Directory of replacement files, named like they are in the mount
+        #[structopt(parse(from_os_str))]
+        files: PathBuf,
+
+        /// Path to write the new arc to
+        #[structopt(parse(from_os_str))]
+        out: PathBuf,
+    },
+
+    /// Replace files in an arc in place, appending the new data instead of rewriting the arc
+    Patch {
+        /// data.arc to modify
+        #[structopt(parse(from_os_str))]
+        arc: PathBuf,
+
+        /// Directory of replacement files, named like they are in the mount
+        #[structopt(parse(from_os_str))]
+        files: PathBuf,
+    },
+
+    /// List the files, directories and streams that differ between two arcs
+    Diff {
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
+
+        #[structopt(parse(from_os_str))]
+        new: PathBuf,
+
+        /// Print the changes as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+
+    /// Extract the files added or modified between two arcs, keeping their arc paths
+    ExportDelta {
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
+
+        #[structopt(parse(from_os_str))]
+        new: PathBuf,
+
+        /// Directory to extract to, or a zip file if it ends in .zip
+        #[structopt(parse(from_os_str))]
+        out: PathBuf,
+    },
+
+    /// Write the table entries of every file in an arc to a JSON or CSV file
+    ExportManifest {
+        #[structopt(parse(from_os_str))]
+        arc: PathBuf,
+
+        /// Path to write the manifest to, as CSV if it ends in .csv and JSON otherwise
+        #[structopt(parse(from_os_str))]
+        out: PathBuf,
+    },
+
+    /// Decompress every file in an arc and check its sizes, offsets and (optionally) hashes
+    Verify {
+        #[structopt(parse(from_os_str))]
+        arc: PathBuf,
+
+        /// Compare the sha256 of every file to the ones saved in this file
+        #[structopt(long, parse(from_os_str))]
+        hashes: Option<PathBuf>,
+
+        /// Save the sha256 of every file to this file, in sha256sum format
+        #[structopt(long, parse(from_os_str))]
+        save_hashes: Option<PathBuf>,
+    },
+
+    /// Compute, look up and search for hash40s
+    Hash(HashCommand),
+
+    /// Find labels for unlabeled hashes in the strings contained in the arc's files, printing
+    /// them as label lines
+    DiscoverLabels {
+        #[structopt(parse(from_os_str))]
+        arc: PathBuf,
+    },
+
+    /// Count the files without labels by parent directory and extension
+    UnknownHashes {
+        #[structopt(parse(from_os_str))]
+        arc: PathBuf,
+
+        /// Also list the hashes in each group
+        #[structopt(long)]
+        list: bool,
+    },
+
+    /// List the files matching a pattern and filters
+    Find(FindArgs),
+
+    /// Print every table row of a file, directory or stream and where its redirects lead
+    Inspect {
+        #[structopt(parse(from_os_str))]
+        arc: PathBuf,
+
+        /// Path as in the mount, or hash40 starting with 0x
+        node: String,
+    },
+
+    /// Write every table of the decompressed file system table to its own file and list where
+    /// they are
+    DumpTables {
+        #[structopt(parse(from_os_str))]
+        arc: PathBuf,
+
+        /// Directory to write `<table>.bin` to
+        #[structopt(parse(from_os_str))]
+        out: PathBuf,
+    },
 }
 
-fn get_args() -> Option<(std::ffi::OsString, std::ffi::OsString)> {
-    Some((
-        env::args_os().nth(1)?,
-        env::args_os().nth(2)?
-    ))
+#[derive(StructOpt)]
+struct FindArgs {
+    #[structopt(parse(from_os_str))]
+    arc: PathBuf,
+
+    /// Glob matched against full paths, unlabeled entries are named 0x<hash>
+    pattern: Option<String>,
+
+    /// Treat the pattern as a regex instead of a glob
+    #[structopt(long)]
+    regex: bool,
+
+    /// Only files with this extension
+    #[structopt(long)]
+    ext: Option<String>,
+
+    /// Minimum decompressed size in bytes
+    #[structopt(long)]
+    min_size: Option<u64>,
+
+    /// Maximum decompressed size in bytes
+    #[structopt(long)]
+    max_size: Option<u64>,
+
+    /// Only compressed files
+    #[structopt(long, conflicts_with = "uncompressed")]
+    compressed: bool,
+
+    /// Only uncompressed files
+    #[structopt(long)]
+    uncompressed: bool,
 }
 
-fn main() {
-    env_logger::init();
-    if let Some((arc_path, mountpoint)) = get_args() {
-        let options = ["-o", "ro", "-o", "fsname=hello", "-o", "auto_unmount", "-o", "allow_other"]
-            .iter()
-            .map(|o| o.as_ref())
-            .collect::<Vec<&OsStr>>();
-        fuse::mount(ArcFS::open(arc_path).unwrap(), &mountpoint, &options).unwrap();
+#[derive(StructOpt)]
+enum HashCommand {
+    /// Print the hash40 of strings
+    Compute {
+        strings: Vec<String>,
+    },
+
+    /// Print the labels of hashes
+    Lookup {
+        hashes: Vec<String>,
+
+        /// Extra files of strings to check, one per line
+        #[structopt(long, parse(from_os_str))]
+        labels: Vec<PathBuf>,
+    },
+
+    /// Find the strings of hashes by expanding path templates such as
+    /// fighter/{name}/model/body/c{00..07}/model.numdlb
+    Search {
+        hashes: Vec<String>,
+
+        /// File of hashes to search for, one per line
+        #[structopt(long, parse(from_os_str))]
+        hash_file: Option<PathBuf>,
+
+        #[structopt(long = "template", required = true)]
+        templates: Vec<String>,
+
+        /// Words for {name} placeholders as name=path, or a path for every other placeholder
+        #[structopt(long = "wordlist")]
+        wordlists: Vec<String>,
+    },
+
+    /// Build a label file like hash40s.tsv from a file of strings, one per line
+    Convert {
+        #[structopt(parse(from_os_str))]
+        strings: PathBuf,
+
+        #[structopt(parse(from_os_str))]
+        out: PathBuf,
+    },
+}
+
+#[derive(StructOpt)]
+struct MountArgs {
+    /// data.arc to mount
+    #[structopt(parse(from_os_str))]
+    arc: PathBuf,
+
+    /// Directory to mount the arc at
+    #[structopt(parse(from_os_str))]
+    mountpoint: PathBuf,
+
+    /// Only load directories when they are first accessed, mounting almost instantly
+    #[structopt(long)]
+    lazy: bool,
+
+    /// Host directory to keep changes to the mount in, making it writable. The arc itself is
+    /// never modified
+    #[structopt(long, parse(from_os_str))]
+    overlay: Option<PathBuf>,
+
+    /// Mod directories laid out like the arc (LayeredFS style) to merge over it read-only.
+    /// When several mods provide the same file the one listed first is used
+    #[structopt(long, parse(from_os_str))]
+    mods: Vec<PathBuf>,
+
+    /// Directory to keep an index of the arc in, so later mounts of the same arc skip parsing.
+    /// Takes priority over --lazy
+    #[structopt(long, parse(from_os_str))]
+    cache_dir: Option<PathBuf>,
+
+    /// Log every FUSE operation with how long it took
+    #[structopt(long)]
+    trace: bool,
+}
+
+fn mount(args: MountArgs) -> io::Result<()> {
+    let mode = if args.overlay.is_some() { "rw" } else { "ro" };
+    let options = ["-o", mode, "-o", "fsname=hello", "-o", "auto_unmount", "-o", "allow_other"];
+    let options = options
+        .iter()
+        .map(|o| o.as_ref())
+        .collect::<Vec<&OsStr>>();
+    let mut fs = if let Some(cache_dir) = &args.cache_dir {
+        ArcFS::open_cached(&args.arc, cache_dir)
+    } else if args.lazy {
+        ArcFS::open_lazy(&args.arc)
+    } else {
+        ArcFS::open(&args.arc)
+    }?;
+    if !args.mods.is_empty() {
+        let (mods, conflicts) = Mods::new(args.mods)?;
+        for conflict in conflicts {
+            let layers: Vec<_> = conflict.layers
+                .iter()
+                .map(|&layer| mods.layers[layer].display().to_string())
+                .collect();
+            warn!(
+                target: "fuse", "Conflict: {} is provided by {}, using {}",
+                conflict.path, layers.join(", "), layers[0]
+            );
+        }
+        fs = fs.with_mods(mods);
+    }
+    if let Some(overlay) = &args.overlay {
+        fs = fs.with_overlay(Overlay::new(overlay)?);
+    }
+    fuse::mount(fs, &args.mountpoint, &options)
+}
+
+/// Read every file under `dir`. Files already in the arc replace it, the others are added
+fn read_changes(arc: &arc::Arc, dir: &Path) -> io::Result<arc::Changes> {
+    let mut paths = Vec::new();
+    mods::walk(dir, "", &mut paths)?;
+
+    let mut changes = arc::Changes::default();
+    for (path, _) in paths.into_iter().filter(|(_, is_dir)| !is_dir) {
+        let data = fs::read(dir.join(&path))?;
+        // Unlabeled files can still be replaced through their full path
+        let existing = arc.get_by_path(&path)
+            .or_else(|| arc.file_path_index(arc::hash40(&path)).map(|_| arc::hash40(&path)));
+        match existing {
+            Some(hash40) => {
+                changes.replaced.insert(hash40, data);
+            }
+            None => changes.added.push((path, data)),
+        }
+    }
+
+    Ok(changes)
+}
+
+fn repack(arc_path: &Path, files: &Path, out: &Path) -> io::Result<()> {
+    if out.exists() && fs::canonicalize(out)? == fs::canonicalize(arc_path)? {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "can't repack an arc into itself"))
+    }
+
+    let arc = arc::Arc::open(arc_path)?;
+    let changes = read_changes(&arc, files)?;
+    println!("Replacing {} files, adding {}", changes.replaced.len(), changes.added.len());
+
+    let mut writer = io::BufWriter::new(fs::File::create(out)?);
+    arc.repack(&changes, &mut writer)
+}
+
+fn patch(arc_path: &Path, files: &Path) -> io::Result<()> {
+    let arc = arc::Arc::open(arc_path)?;
+    let changes = read_changes(&arc, files)?;
+    println!("Replacing {} files, adding {}", changes.replaced.len(), changes.added.len());
+
+    // Only the header is overwritten, everything else is appended past the mapped data
+    let file = fs::OpenOptions::new().write(true).open(arc_path)?;
+    let mut writer = io::BufWriter::new(file);
+    arc.patch(&changes, &mut writer)
+}
+
+fn print_changes(kind: &str, changes: &[arc::FileChange]) {
+    for change in changes {
+        let symbol = match change.change {
+            arc::Change::Added => '+',
+            arc::Change::Removed => '-',
+            arc::Change::Modified => '~',
+        };
+        let name = change.display_path();
+        let sizes = |version: Option<&arc::diff::FileVersion>| {
+            version
+                .map(|version| format!("{}/{} {:08x}", version.comp_size, version.decomp_size, version.content_hash))
+                .unwrap_or_else(|| String::from("none"))
+        };
+        println!("{} {} {} ({} -> {})", symbol, kind, name, sizes(change.old.as_ref()), sizes(change.new.as_ref()));
+    }
+}
+
+fn diff(old: &Path, new: &Path, json: bool) -> io::Result<()> {
+    let old = arc::Arc::open(old)?;
+    let new = arc::Arc::open(new)?;
+    let diff = old.diff(&new)?;
+
+    if json {
+        serde_json::to_writer_pretty(io::stdout().lock(), &diff)?;
+        println!();
+        return Ok(())
+    }
+
+    print_changes("file", &diff.files);
+    for dir in &diff.directories {
+        let name = dir.path.map(String::from).unwrap_or_else(|| format!("0x{:010x}", dir.hash40));
+        match dir.change {
+            arc::Change::Added => println!("+ dir {}", name),
+            arc::Change::Removed => println!("- dir {}", name),
+            arc::Change::Modified => println!("~ dir {}", name),
+        }
+        for child in &dir.added {
+            println!("    + {}", child);
+        }
+        for child in &dir.removed {
+            println!("    - {}", child);
+        }
+    }
+    print_changes("stream", &diff.streams);
+    println!(
+        "{} files, {} directories and {} streams changed",
+        diff.files.len(), diff.directories.len(), diff.streams.len()
+    );
+
+    Ok(())
+}
+
+fn export_delta(old: &Path, new: &Path, out: &Path) -> io::Result<()> {
+    let old = arc::Arc::open(old)?;
+    let new = arc::Arc::open(new)?;
+    let diff = old.diff(&new)?;
+
+    let mut output = export::Output::create(out)?;
+    let count = export::export_delta(&new, &diff, &mut output)?;
+    output.finish()?;
+
+    let removed = diff.files.iter().chain(&diff.streams).filter(|change| change.change == arc::Change::Removed);
+    for change in removed {
+        println!("Removed: {}", change.path.map(String::from).unwrap_or_else(|| format!("0x{:010x}", change.hash40)));
+    }
+    println!("Exported {} files to {}", count, out.display());
+
+    Ok(())
+}
+
+fn export_manifest(arc_path: &Path, out: &Path) -> io::Result<()> {
+    let arc = arc::Arc::open(arc_path)?;
+    let manifest = arc.manifest();
+    let writer = io::BufWriter::new(fs::File::create(out)?);
+
+    if out.extension().is_some_and(|ext| ext == "csv") {
+        let mut writer = csv::Writer::from_writer(writer);
+        for entry in &manifest {
+            writer.serialize(entry)?;
+        }
+        writer.flush()?;
+    } else {
+        serde_json::to_writer_pretty(writer, &manifest)?;
+    }
+    println!("Wrote {} entries to {}", manifest.len(), out.display());
+
+    Ok(())
+}
+
+/// Returns whether the arc is free of problems
+fn verify(arc_path: &Path, hashes: Option<&Path>, save_hashes: Option<&Path>) -> io::Result<bool> {
+    let arc = arc::Arc::open(arc_path)?;
+    let expected = match hashes {
+        Some(path) => Some(arc::verify::read_hashes(fs::File::open(path)?)?),
+        None => None,
+    };
+
+    let report = arc.verify(save_hashes.is_some(), expected.as_ref());
+    for problem in &report.problems {
+        println!("{}", problem);
+    }
+    if let Some(path) = save_hashes {
+        arc::verify::write_hashes(io::BufWriter::new(fs::File::create(path)?), &report.hashes)?;
+    }
+    println!("Checked {} files, {} problems", report.checked, report.problems.len());
+
+    Ok(report.problems.is_empty())
+}
+
+fn hash(command: HashCommand) -> io::Result<()> {
+    match command {
+        HashCommand::Compute { strings } => {
+            for string in strings {
+                println!("0x{:010x} {}", arc::hash40(&string), string);
+            }
+        }
+        HashCommand::Lookup { hashes, labels } => {
+            let mut names: HashMap<u64, String> = arc::Arc::load_hashes()
+                .into_iter()
+                .map(|(hash40, name)| (hash40, name.to_owned()))
+                .collect();
+            for path in labels {
+                names.extend(hash::read_lines(path)?.into_iter().map(|name| (arc::hash40(&name), name)));
+            }
+            for hash40 in hashes {
+                let hash40 = hash::parse_hash40(&hash40)?;
+                match names.get(&hash40) {
+                    Some(name) => println!("0x{:010x} {}", hash40, name),
+                    None => println!("0x{:010x} not found", hash40),
+                }
+            }
+        }
+        HashCommand::Search { hashes, hash_file, templates, wordlists } => {
+            let mut targets = hashes
+                .iter()
+                .map(|hash40| hash::parse_hash40(hash40))
+                .collect::<io::Result<std::collections::HashSet<u64>>>()?;
+            if let Some(path) = hash_file {
+                for hash40 in hash::read_lines(path)? {
+                    targets.insert(hash::parse_hash40(&hash40)?);
+                }
+            }
+
+            let mut words = HashMap::new();
+            for wordlist in wordlists {
+                let (name, path) = wordlist.split_once('=').unwrap_or(("", &wordlist));
+                words.insert(name.to_owned(), hash::read_lines(path)?);
+            }
+
+            for template_str in templates {
+                let template = hash::Template::parse(&template_str, &words)?;
+                info!(target: "hash", "Trying up to {} strings for {}", template.count(), template_str);
+                for (hash40, string) in template.search(&targets) {
+                    println!("{:X}\t{}", hash40, string);
+                }
+            }
+        }
+        HashCommand::Convert { strings, out } => {
+            let strings = hash::read_lines(strings)?;
+            hash::write_labels(io::BufWriter::new(fs::File::create(out)?), &strings)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn discover_labels(arc_path: &Path) -> io::Result<()> {
+    let arc = arc::Arc::open(arc_path)?;
+    let unlabeled = arc.unlabeled_hashes().len();
+    let labels = arc.discover_labels();
+    for (hash40, label) in &labels {
+        println!("{:X}\t{}", hash40, label);
+    }
+    info!(target: "hash", "Found labels for {} of {} unlabeled hashes", labels.len(), unlabeled);
+
+    Ok(())
+}
+
+fn unknown_hashes(arc_path: &Path, list: bool) -> io::Result<()> {
+    let arc = arc::Arc::open(arc_path)?;
+    let groups = arc.unlabeled_files();
+    let unlabeled: usize = groups.iter().map(|group| group.hashes.len()).sum();
+
+    for group in &groups {
+        println!("{:>8}  {}  {}", group.hashes.len(), arc.display_path(group.parent), arc.display_path(group.ext));
+        if list {
+            for hash40 in &group.hashes {
+                println!("          0x{:010x}", hash40);
+            }
+        }
+    }
+    println!(
+        "{} of {} files unlabeled",
+        unlabeled, arc.tables.file_info_paths.len() + arc.tables.stream_entries.len()
+    );
+
+    Ok(())
+}
+
+fn find(args: FindArgs) -> io::Result<()> {
+    let pattern = match &args.pattern {
+        Some(pattern) if args.regex => Some(arc::find::Pattern::regex(pattern)?),
+        Some(pattern) => Some(arc::find::Pattern::glob(pattern)?),
+        None => None,
+    };
+    let compressed = match (args.compressed, args.uncompressed) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    };
+    let query = arc::find::Query {
+        pattern,
+        extension: args.ext,
+        min_size: args.min_size,
+        max_size: args.max_size,
+        compressed,
+    };
+
+    let arc = arc::Arc::open(&args.arc)?;
+    for found in arc.find(&query) {
+        println!("0x{:010x} {:>10} {}", found.hash40, found.decomp_size, found.path);
+    }
+
+    Ok(())
+}
+
+fn inspect(arc_path: &Path, node: &str) -> io::Result<()> {
+    let arc = arc::Arc::open(arc_path)?;
+    let hash40 = if node.starts_with("0x") {
+        hash::parse_hash40(node)?
     } else {
-        eprintln!("Missing arg [mountpoint]");
+        arc.get_by_path(node).unwrap_or_else(|| arc::hash40(node))
+    };
+
+    print!("{}", arc.inspect(hash40)?);
+    Ok(())
+}
+
+fn dump_tables(arc_path: &Path, out: &Path) -> io::Result<()> {
+    let arc = arc::Arc::open(arc_path)?;
+    let infos = arc.dump_tables(out)?;
+
+    println!("{:<24} {:>10} {:>10} {:>6}", "table", "offset", "count", "size");
+    for info in infos {
+        println!("{:<24} {:>#10x} {:>10} {:>#6x}", info.name, info.offset, info.count, info.element_size);
+    }
+    Ok(())
+}
+
+/// Log to stderr, at the level set by `RUST_LOG` (`info` by default). Targets are `parser`,
+/// `cache`, `fuse` and `fuse::op` for the per-operation trace
+fn init_logger(trace: bool) {
+    let mut builder = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+    if trace {
+        builder.filter(Some(trace::TARGET), log::LevelFilter::Trace);
+    }
+    builder.init();
+}
+
+fn main() {
+    let args = Args::from_args();
+    init_logger(matches!(&args, Args::Mount(args) if args.trace));
+    let result = match args {
+        Args::Mount(args) => mount(args),
+        Args::Repack { arc, files, out } => repack(&arc, &files, &out),
+        Args::Patch { arc, files } => patch(&arc, &files),
+        Args::Diff { old, new, json } => diff(&old, &new, json),
+        Args::ExportDelta { old, new, out } => export_delta(&old, &new, &out),
+        Args::ExportManifest { arc, out } => export_manifest(&arc, &out),
+        Args::DiscoverLabels { arc } => discover_labels(&arc),
+        Args::UnknownHashes { arc, list } => unknown_hashes(&arc, list),
+        Args::Find(args) => find(args),
+        Args::Inspect { arc, node } => inspect(&arc, &node),
+        Args::DumpTables { arc, out } => dump_tables(&arc, &out),
+        Args::Hash(command) => hash(command),
+        // Problems are printed by verify itself and only change the exit code
+        Args::Verify { arc, hashes, save_hashes } => {
+            match verify(&arc, hashes.as_deref(), save_hashes.as_deref()) {
+                Ok(false) => std::process::exit(2),
+                result => result.map(|_| ()),
+            }
+        }
+    };
+
+    if let Err(err) = result {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn error_codes() {
+        assert_eq!(io_error_code(io::Error::new(io::ErrorKind::InvalidData, "bad zstd frame")), EIO);
+        assert_eq!(io_error_code(io::Error::new(io::ErrorKind::NotFound, "no such file")), ENOENT);
+        assert_eq!(io_error_code(io::Error::from_raw_os_error(EROFS)), EROFS);
     }
 }
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A file provided by more than one mod directory
+pub struct Conflict {
+    pub path: String,
+    /// Indices of the layers providing the file, the first one is used
+    pub layers: Vec<usize>,
+}
+
+/// Read-only mod directories laid out like the arc (LayeredFS style), merged on top of it.
+/// Layers earlier in the list take precedence over later ones
+pub struct Mods {
+    pub layers: Vec<PathBuf>,
+    /// Arc path of every file and directory provided by the mods, and the layer it is used from
+    files: HashMap<String, usize>,
+    /// Names of the children of every directory, merged across layers
+    dir_children: HashMap<String, Vec<(String, bool)>>,
+}
+
+impl Mods {
+    /// Index every mod directory in `layers`, returning the files provided by multiple mods
+    pub fn new(layers: Vec<PathBuf>) -> io::Result<(Self, Vec<Conflict>)> {
+        let mut mods = Mods {
+            layers: Vec::new(),
+            files: HashMap::new(),
+            dir_children: HashMap::new(),
+        };
+        let mut providers: HashMap<String, Vec<usize>> = HashMap::new();
+
+        for (layer, root) in layers.iter().enumerate() {
+            let mut paths = Vec::new();
+            walk(root, "", &mut paths)?;
+            for (path, is_dir) in paths {
+                if !is_dir {
+                    providers.entry(path.clone()).or_default().push(layer);
+                }
+                if !mods.files.contains_key(&path) {
+                    let (parent, name) = split_path(&path);
+                    mods.dir_children
+                        .entry(parent.to_owned())
+                        .or_default()
+                        .push((name.to_owned(), is_dir));
+                    mods.files.insert(path, layer);
+                }
+            }
+        }
+        mods.layers = layers;
+
+        let mut conflicts: Vec<_> = providers
+            .into_iter()
+            .filter(|(_, layers)| layers.len() > 1)
+            .map(|(path, layers)| Conflict { path, layers })
+            .collect();
+        conflicts.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok((mods, conflicts))
+    }
+
+    /// Get the host file or directory providing `path`, if any mod does
+    pub fn get(&self, path: &str) -> Option<PathBuf> {
+        let layer = *self.files.get(path)?;
+        Some(self.layers[layer].join(path))
+    }
+
+    /// Mod directory `path` is used from
+    pub fn layer(&self, path: &str) -> Option<&Path> {
+        self.files.get(path).map(|&layer| self.layers[layer].as_path())
+    }
+
+    /// Names of the files the mods provide in the directory `path`
+    pub fn read_dir(&self, path: &str) -> &[(String, bool)] {
+        self.dir_children.get(path).map(|children| &children[..]).unwrap_or(&[])
+    }
+}
+
+fn split_path(path: &str) -> (&str, &str) {
+    match path.rfind('/') {
+        Some(i) => (&path[..i], &path[i + 1..]),
+        None => ("", path),
+    }
+}
+
+/// Recursively list everything under `dir` as arc paths relative to the mod root
+pub fn walk(dir: &Path, prefix: &str, paths: &mut Vec<(String, bool)>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(name) => {
+                warn!(target: "fuse", "Skipping non-utf8 mod file {:?}", name);
+                continue
+            }
+        };
+        let path = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+        let is_dir = entry.file_type()?.is_dir();
+        paths.push((path.clone(), is_dir));
+        if is_dir {
+            walk(&entry.path(), &path, paths)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn layer_precedence() {
+        let root = std::env::temp_dir().join(format!("arc-fuse-mods-{}", std::process::id()));
+        let (first, second) = (root.join("first"), root.join("second"));
+        fs::create_dir_all(first.join("fighter/mario")).unwrap();
+        fs::create_dir_all(second.join("fighter/mario")).unwrap();
+        fs::create_dir_all(second.join("ui")).unwrap();
+        fs::write(first.join("fighter/mario/model.numdlb"), b"first").unwrap();
+        fs::write(second.join("fighter/mario/model.numdlb"), b"second").unwrap();
+        fs::write(second.join("fighter/mario/body.nutexb"), b"second").unwrap();
+
+        let (mods, conflicts) = Mods::new(vec![first.clone(), second.clone()]).unwrap();
+
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "fighter/mario/model.numdlb");
+        assert_eq!(conflicts[0].layers, vec![0, 1]);
+        assert_eq!(mods.get("fighter/mario/model.numdlb"), Some(first.join("fighter/mario/model.numdlb")));
+        assert_eq!(mods.layer("fighter/mario/body.nutexb"), Some(second.as_path()));
+        assert_eq!(mods.get("fighter/mario/missing.numdlb"), None);
+
+        let mut children = mods.read_dir("fighter/mario").to_vec();
+        children.sort();
+        assert_eq!(children, vec![
+            (String::from("body.nutexb"), false),
+            (String::from("model.numdlb"), false),
+        ]);
+        let mut root_children = mods.read_dir("").to_vec();
+        root_children.sort();
+        assert_eq!(root_children, vec![(String::from("fighter"), true), (String::from("ui"), true)]);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Prefix of the marker files recording that a file from the arc was deleted
+const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// Whether `name` is reserved for whiteout markers, so it can't be looked up or created
+/// through the mount
+pub fn is_reserved(name: &OsStr) -> bool {
+    name.to_string_lossy().starts_with(WHITEOUT_PREFIX)
+}
+
+/// A host directory laid out like the arc, holding every change made to the mount so the arc
+/// itself is never written to
+pub struct Overlay {
+    root: PathBuf,
+}
+
+impl Overlay {
+    pub fn new<P: AsRef<Path>>(root: P) -> io::Result<Self> {
+        fs::create_dir_all(root.as_ref())?;
+        Ok(Overlay { root: root.as_ref().to_owned() })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Host path of the arc path `path`
+    pub fn path(&self, path: &str) -> PathBuf {
+        self.root.join(path)
+    }
+
+    fn whiteout_path(&self, path: &str) -> PathBuf {
+        let (parent, name) = match path.rfind('/') {
+            Some(i) => (&path[..i], &path[i + 1..]),
+            None => ("", path),
+        };
+        self.root.join(parent).join(String::from(WHITEOUT_PREFIX) + name)
+    }
+
+    /// Get the host file or directory overriding `path`, if there is one
+    pub fn get(&self, path: &str) -> Option<PathBuf> {
+        let host_path = self.path(path);
+        if path.is_empty() || !host_path.exists() {
+            None
+        } else {
+            Some(host_path)
+        }
+    }
+
+    /// Whether `path` has been deleted from the arc
+    pub fn is_deleted(&self, path: &str) -> bool {
+        self.whiteout_path(path).exists()
+    }
+
+    /// Mark `path` as deleted from the arc
+    pub fn delete(&self, path: &str) -> io::Result<()> {
+        let whiteout = self.whiteout_path(path);
+        fs::create_dir_all(whiteout.parent().unwrap())?;
+        fs::File::create(whiteout).map(|_| ())
+    }
+
+    fn undelete(&self, path: &str) -> io::Result<()> {
+        match fs::remove_file(self.whiteout_path(path)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(())
+        }
+    }
+
+    /// Create (or truncate) the file `path`, filled with `data`
+    pub fn create_file(&self, path: &str, data: &[u8]) -> io::Result<PathBuf> {
+        let host_path = self.path(path);
+        fs::create_dir_all(host_path.parent().unwrap())?;
+        fs::write(&host_path, data)?;
+        self.undelete(path)?;
+        Ok(host_path)
+    }
+
+    pub fn create_dir(&self, path: &str) -> io::Result<PathBuf> {
+        let host_path = self.path(path);
+        fs::create_dir_all(&host_path)?;
+        self.undelete(path)?;
+        Ok(host_path)
+    }
+
+    /// Names of the files in the overlay directory `path`, without the whiteouts
+    pub fn read_dir(&self, path: &str) -> Vec<(String, bool)> {
+        fs::read_dir(self.path(path))
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| {
+                        let entry = entry.ok()?;
+                        let name = entry.file_name().into_string().ok()?;
+                        if name.starts_with(WHITEOUT_PREFIX) {
+                            None
+                        } else {
+                            Some((name, entry.file_type().ok()?.is_dir()))
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn whiteouts() {
+        let root = std::env::temp_dir().join(format!("arc-fuse-overlay-{}", std::process::id()));
+        let overlay = Overlay::new(&root).unwrap();
+
+        overlay.create_file("fighter/mario/model.numdlb", b"test").unwrap();
+        assert_eq!(overlay.get("fighter/mario/model.numdlb"), Some(root.join("fighter/mario/model.numdlb")));
+        assert_eq!(overlay.get("fighter/mario/missing.numdlb"), None);
+
+        overlay.delete("fighter/mario/body.nutexb").unwrap();
+        assert!(overlay.is_deleted("fighter/mario/body.nutexb"));
+        assert!(!overlay.is_deleted("fighter/mario/model.numdlb"));
+        assert_eq!(overlay.read_dir("fighter/mario"), vec![(String::from("model.numdlb"), false)]);
+
+        overlay.create_file("fighter/mario/body.nutexb", &[]).unwrap();
+        assert!(!overlay.is_deleted("fighter/mario/body.nutexb"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn reserved_names() {
+        assert!(is_reserved(OsStr::new(".wh.model.numdlb")));
+        assert!(!is_reserved(OsStr::new("model.numdlb")));
+        assert!(!is_reserved(OsStr::new(".whmodel.numdlb")));
+    }
+}
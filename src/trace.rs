@@ -0,0 +1,28 @@
+use std::time::Instant;
+
+/// Log target of the per-operation trace of FUSE calls, enabled with `mount --trace` or
+/// `RUST_LOG=fuse::op=trace`
+pub const TARGET: &str = "fuse::op";
+
+/// Logs a FUSE operation and how long it took when dropped
+pub struct OpTrace {
+    op: String,
+    start: Instant,
+}
+
+impl OpTrace {
+    /// Start timing an operation, `op` is only formatted if the trace is enabled
+    pub fn start<F: FnOnce() -> String>(op: F) -> Option<Self> {
+        if log_enabled!(target: TARGET, log::Level::Trace) {
+            Some(OpTrace { op: op(), start: Instant::now() })
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for OpTrace {
+    fn drop(&mut self) {
+        trace!(target: TARGET, "{} took {:?}", self.op, self.start.elapsed());
+    }
+}
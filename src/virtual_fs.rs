@@ -0,0 +1,321 @@
+use super::*;
+use std::collections::BTreeMap;
+
+/// Directory of the files describing the arc itself
+const INFO_DIR: &str = ".arc";
+
+const INFO_FILES: &[&str] = &["header.json", "stats.json", "unknown_hashes.txt"];
+
+/// Directory resolving names like `0x1234abcd56` to the file with that hash40
+const BY_HASH_DIR: &str = ".by-hash";
+
+/// Directory with a directory per extension, holding every file with that extension
+const BY_EXT_DIR: &str = ".by-ext";
+
+/// Files by extension and then by name, see `extension_index`
+pub type ExtensionIndex = BTreeMap<String, BTreeMap<String, u64>>;
+
+/// Mirror of the arc's tree with the data of every file as it's stored, still compressed
+const RAW_DIR: &str = ".raw";
+
+/// Top-level directories generated by the mount instead of read from the arc. They can't be
+/// written to and hide anything in the arc or the overlay with the same name
+const VIRTUAL_DIRS: &[&str] = &[INFO_DIR, BY_HASH_DIR, BY_EXT_DIR, RAW_DIR];
+
+/// Whether `path` is in one of the virtual directories
+pub fn is_virtual(path: &str) -> bool {
+    VIRTUAL_DIRS.contains(&path.split('/').next().unwrap_or(path))
+}
+
+fn join(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
+
+/// Name of every file in `.by-ext/<ext>/`, the file name prefixed by the hash of its parent
+/// directory so files with the same name don't collide. Files without an extension aren't
+/// listed, unlabeled extensions and names are shown as `0x<hash>`
+fn extension_index(arc: &arc::Arc) -> ExtensionIndex {
+    let name = |hash40: u64| match arc.get_name(hash40) {
+        Some(name) => name.to_owned(),
+        None => format!("0x{:010x}", hash40),
+    };
+
+    let no_ext = arc::hash40("");
+    let mut index = ExtensionIndex::new();
+    for path in arc.tables.file_info_paths.iter().filter(|path| path.ext.hash40() != no_ext) {
+        // Same fallbacks as the names in the rest of the mount
+        let file_name = match (arc.get_name(path.file_name.hash40()), arc.get_name(path.path.hash40())) {
+            (Some(file_name), _) => file_name.to_owned(),
+            (None, Some(label)) => label.rsplit('/').next().unwrap().to_owned(),
+            (None, None) => name(path.file_name.hash40()),
+        };
+        index.entry(name(path.ext.hash40()))
+            .or_default()
+            .insert(format!("0x{:010x}-{}", path.parent.hash40(), file_name), path.path.hash40());
+    }
+
+    index
+}
+
+fn to_json(value: serde_json::Value) -> Vec<u8> {
+    let mut json = serde_json::to_vec_pretty(&value).unwrap_or_default();
+    json.push(b'\n');
+    json
+}
+
+impl ArcFS {
+    /// Content of the virtual file at `path`
+    fn generate(&self, path: &str) -> Option<Vec<u8>> {
+        let name = path.strip_prefix(INFO_DIR)?.strip_prefix('/')?;
+        match name {
+            "header.json" => Some(to_json(self.arc.header_json())),
+            "stats.json" => Some(to_json(self.arc.stats_json())),
+            "unknown_hashes.txt" => Some(self.arc.unknown_hashes_text().into_bytes()),
+            _ => None,
+        }
+    }
+
+    /// Entries of the virtual directory at `path`, the root only lists the virtual directories
+    pub fn virtual_children(&self, path: &str) -> Option<Vec<(u64, FileType, String)>> {
+        let (names, kind) = match path {
+            "" => (VIRTUAL_DIRS, FileType::Directory),
+            INFO_DIR => (INFO_FILES, FileType::RegularFile),
+            // Listing every file would make the directory unusable with most tools
+            BY_HASH_DIR => (&[][..], FileType::RegularFile),
+            BY_EXT_DIR => {
+                let exts = self.extensions.iter().flat_map(|index| index.keys());
+                return Some(exts.map(|ext| (arc::hash40(&join(path, ext)), FileType::Directory, ext.clone())).collect())
+            }
+            _ if path.starts_with(RAW_DIR) => {
+                let dir = *self.raw.get(&arc::hash40(path))?;
+                let children = self.arc.dir_children.get(&dir)?;
+                return Some(children.iter().map(|child| {
+                    let stem = self.arc.stems[child];
+                    let kind = match self.arc.files.get(child) {
+                        Some(arc::ArcFileInfo::Directory) => FileType::Directory,
+                        _ => FileType::RegularFile,
+                    };
+                    (arc::hash40(&join(path, stem)), kind, stem.to_owned())
+                }).collect())
+            }
+            _ => {
+                let ext = path.strip_prefix(BY_EXT_DIR)?.strip_prefix('/')?;
+                let files = self.extensions.as_ref()?.get(ext)?;
+                return Some(files.iter().map(|(name, &hash40)| (hash40, FileType::RegularFile, name.clone())).collect())
+            }
+        };
+
+        Some(names.iter().map(|name| (arc::hash40(&join(path, name)), kind, name.to_string())).collect())
+    }
+
+    /// Look up the virtual entry at `path`
+    pub fn lookup_virtual(&mut self, req: &Request, path: String) -> Option<FileAttr> {
+        let ino = self.resolve_virtual(path)?;
+        self.virtual_attr(req, ino).or_else(|| self.attr(req, ino))
+    }
+
+    /// Inode of the virtual entry at `path`. Files are generated here and kept until they're
+    /// looked up again, so their size and content match while they're open
+    fn resolve_virtual(&mut self, path: String) -> Option<u64> {
+        if let Some(name) = path.strip_prefix(BY_HASH_DIR).and_then(|name| name.strip_prefix('/')) {
+            // Entries of directories looked up by hash keep the hash in their path
+            let mut components = name.split('/');
+            let mut hash40 = hash::parse_hash40(components.next()?).ok()?;
+            for name in components {
+                self.arc.load_dir(hash40);
+                hash40 = self.arc.get_child(hash40, name)?;
+            }
+            return self.resolve_hash(hash40, &path)
+        }
+        if path.starts_with(RAW_DIR) {
+            return self.resolve_raw(path)
+        }
+        if path.starts_with(BY_EXT_DIR) && self.extensions.is_none() {
+            self.arc.load_dir(0);
+            self.extensions = Some(extension_index(&self.arc));
+        }
+        if let Some((dir, name)) = path.rsplit_once('/').filter(|(dir, _)| dir.starts_with(BY_EXT_DIR)) {
+            if dir != BY_EXT_DIR {
+                let hash40 = *self.extensions.as_ref()?.get(dir.split_once('/')?.1)?.get(name)?;
+                return self.resolve_hash(hash40, &path)
+            }
+        }
+
+        let ino = arc::hash40(&path);
+        if self.virtual_children(&path).is_none() {
+            let data = self.generate(&path)?;
+            self.virtual_files.insert(ino, data);
+        }
+        self.paths.insert(ino, path);
+
+        Some(ino)
+    }
+
+    /// Look up the file or directory `hash40` in the arc. It keeps its own inode, so it's the
+    /// same entry as under its path. Entries the mount doesn't know the path of yet are
+    /// registered under their label, or under `path` (which can't be written to) if they don't
+    /// have one
+    fn resolve_hash(&mut self, hash40: u64, path: &str) -> Option<u64> {
+        self.arc.load_file(hash40);
+        self.arc.files.get(&hash40)?;
+
+        if !self.paths.contains_key(&hash40) {
+            let label = self.arc.get_name(hash40).unwrap_or(path);
+            self.paths.insert(hash40, label.to_owned());
+        }
+
+        Some(hash40)
+    }
+
+    /// Look up `path` in the raw mirror, its inode is the hash40 of `path` so it doesn't
+    /// collide with the decompressed file
+    fn resolve_raw(&mut self, path: String) -> Option<u64> {
+        let hash40 = match path.split_once('/') {
+            Some((_, arc_path)) => {
+                let (parent, name) = match arc_path.rsplit_once('/') {
+                    Some((parent, name)) => (*self.raw.get(&arc::hash40(&join(RAW_DIR, parent)))?, name),
+                    None => (0, arc_path),
+                };
+                self.arc.load_dir(parent);
+                self.arc.get_child(parent, name)?
+            }
+            None => 0,
+        };
+        self.arc.load_dir(hash40);
+
+        let ino = arc::hash40(&path);
+        self.raw.insert(ino, hash40);
+        self.paths.insert(ino, path);
+
+        Some(ino)
+    }
+
+    /// Data of the file `hash40` as it's stored in the arc
+    fn raw_data(&self, hash40: u64) -> Option<&[u8]> {
+        match *self.arc.files.get(&hash40)? {
+            arc::ArcFileInfo::Compressed { data, .. } | arc::ArcFileInfo::Uncompressed { data, .. } => {
+                Some(self.arc.slice(data))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn virtual_path(&self, ino: u64) -> Option<&str> {
+        self.paths.get(&ino).map(String::as_str).filter(|path| is_virtual(path))
+    }
+
+    pub fn virtual_attr(&self, req: &Request, ino: u64) -> Option<FileAttr> {
+        let path = self.virtual_path(ino)?;
+        let data = self.virtual_files.get(&ino)
+            .map(Vec::as_slice)
+            .or_else(|| self.raw_data(*self.raw.get(&ino)?));
+        let mut attr = match data {
+            Some(data) => file_attr(req, ino, FileType::RegularFile, data.len() as u64),
+            None if self.virtual_children(path).is_some() => file_attr(req, ino, FileType::Directory, 0),
+            None => return None,
+        };
+        attr.perm &= 0o555;
+
+        Some(attr)
+    }
+
+    pub fn virtual_data(&self, ino: u64) -> Option<&[u8]> {
+        self.virtual_path(ino)?;
+        self.virtual_files.get(&ino)
+            .map(Vec::as_slice)
+            .or_else(|| self.raw_data(*self.raw.get(&ino)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn virtual_paths() {
+        assert!(is_virtual(".arc"));
+        assert!(is_virtual(".arc/stats.json"));
+        assert!(!is_virtual(".arcs/stats.json"));
+        assert!(!is_virtual("fighter/.arc"));
+        assert!(!is_virtual(""));
+        assert!(is_virtual(".by-hash/0x1234abcd56"));
+        assert!(is_virtual(".by-ext/nutexb/0x1234abcd56-def_mario_001_col.nutexb"));
+        assert!(is_virtual(".raw/fighter/mario/model/body/c00/model.numdlb"));
+    }
+
+    fn by_hash(hash40: u64) -> String {
+        format!("{}/0x{:010x}", BY_HASH_DIR, hash40)
+    }
+
+    #[test]
+    fn by_extension() {
+        let arc = arc::fixture::TestArc::new()
+            .file("fighter/mario/model.numdlb", b"mario model")
+            .file("fighter/luigi/model.numdlb", b"luigi model")
+            .file("fighter/mario/body.nutexb", b"mario texture")
+            .file("fighter/mario/hidden.nutexb", b"unlabeled texture")
+            .file("fighter/mario/motion_list", b"no extension")
+            .file("fighter/mario/data.bntx", b"unlabeled extension")
+            .unlabeled("fighter/mario/hidden.nutexb")
+            .unlabeled("fighter/mario/data.bntx")
+            .open("by_extension");
+
+        let name = |parent: &str, file_name: &str| format!("0x{:010x}-{}", arc::hash40(parent), file_name);
+        let hash = |label: &str| format!("0x{:010x}", arc::hash40(label));
+        let mut expected = ExtensionIndex::new();
+        expected.entry(String::from("numdlb")).or_default().extend(vec![
+            (name("fighter/mario", "model.numdlb"), arc::hash40("fighter/mario/model.numdlb")),
+            (name("fighter/luigi", "model.numdlb"), arc::hash40("fighter/luigi/model.numdlb")),
+        ]);
+        expected.entry(String::from("nutexb")).or_default().extend(vec![
+            (name("fighter/mario", "body.nutexb"), arc::hash40("fighter/mario/body.nutexb")),
+            (name("fighter/mario", &hash("hidden.nutexb")), arc::hash40("fighter/mario/hidden.nutexb")),
+        ]);
+        expected.entry(hash("bntx")).or_default().extend(vec![
+            (name("fighter/mario", &hash("data.bntx")), arc::hash40("fighter/mario/data.bntx")),
+        ]);
+
+        assert_eq!(extension_index(&arc), expected);
+    }
+
+    #[test]
+    fn raw_data() {
+        let mut fs = ArcFS::new(arc::fixture::sample().open_lazy("raw_data"));
+        let hash40 = arc::hash40("fighter/mario/model.numdlb");
+        for dir in &[".raw", ".raw/fighter", ".raw/fighter/mario"] {
+            assert!(fs.resolve_virtual(dir.to_string()).is_some());
+        }
+        let ino = fs.resolve_virtual(String::from(".raw/fighter/mario/model.numdlb")).unwrap();
+        assert_ne!(ino, hash40);
+
+        // Stored as a zstd frame, read decompressed through the normal path
+        let raw = fs.virtual_data(ino).unwrap().to_vec();
+        assert_eq!(&raw[..4], b"\x28\xb5\x2f\xfd");
+        let data = fs.arc.get_file_data(hash40).unwrap();
+        assert_eq!(data.get_slice(), &b"mario model ".repeat(16)[..]);
+        assert_eq!(zstd::decode_all(&raw[..]).unwrap(), data.get_slice());
+        assert_eq!(fs.virtual_data(hash40), None);
+    }
+
+    #[test]
+    fn lookup_by_hash() {
+        let mut fs = ArcFS::new(arc::fixture::sample().open_lazy("lookup_by_hash"));
+
+        let file = arc::hash40("fighter/mario/model.numdlb");
+        assert_eq!(fs.resolve_virtual(by_hash(file)), Some(file));
+        assert_eq!(fs.paths[&file], "fighter/mario/model.numdlb");
+
+        let dir = arc::hash40("fighter/mario");
+        assert_eq!(fs.resolve_virtual(by_hash(dir)), Some(dir));
+        let child = format!("{}/body.nutexb", by_hash(dir));
+        assert_eq!(fs.resolve_virtual(child), Some(arc::hash40("fighter/mario/body.nutexb")));
+
+        // Replied to with ENOENT
+        assert_eq!(fs.resolve_virtual(by_hash(arc::hash40("fighter/peach"))), None);
+        assert_eq!(fs.resolve_virtual(format!("{}/not-a-hash", BY_HASH_DIR)), None);
+    }
+}